@@ -0,0 +1,112 @@
+//! Uniform sampling from key material.
+
+use crate::KeySource;
+
+/// Reads bits from a stream of bytes.
+pub struct Bits<I: Iterator<Item = u8>> {
+  bytes: I,
+  byte: u8,
+  /// The number of unread bits left in `byte`.
+  left: u32,
+}
+
+impl<I: Iterator<Item = u8>> Bits<I> {
+  pub fn new(bytes: I) -> Self {
+    Self {
+      bytes,
+      byte: 0,
+      left: 0,
+    }
+  }
+
+  fn take(&mut self, n: usize) -> usize {
+    let mut value = 0;
+    for _ in 0..n {
+      if self.left == 0 {
+        self.byte = self.bytes.next().expect("ran out of key material");
+        self.left = 8;
+      }
+      self.left -= 1;
+      value = value << 1 | (self.byte >> self.left & 1) as usize;
+    }
+    value
+  }
+
+  /// Returns a number below `n` with every number equally likely.  Draws just
+  /// enough bits for the numbers below `n` and rejects the ones that are too
+  /// large.
+  pub fn uniform(&mut self, n: usize) -> usize {
+    let width = n.next_power_of_two().trailing_zeros() as usize;
+    loop {
+      let value = self.take(width);
+      if value < n {
+        return value;
+      }
+    }
+  }
+}
+
+impl<I: Iterator<Item = u8>> Drop for Bits<I> {
+  fn drop(&mut self) {
+    self.byte = 0;
+  }
+}
+
+/// The endless stream of key material given by `KeySource::stream`.  It
+/// comes in blocks of 32 bytes, each derived from the fields and the index of
+/// the block, so that streams of different fields share no bytes however far
+/// they are read.
+pub struct Stream<'a> {
+  key_source: &'a KeySource,
+  label: &'static str,
+  fields: Vec<String>,
+  block: [u8; 32],
+  /// The index of the next block.
+  index: u64,
+  /// The index of the next unread byte of `block`.
+  position: usize,
+}
+
+impl<'a> Stream<'a> {
+  pub fn new(
+    key_source: &'a KeySource,
+    label: &'static str,
+    fields: &[&str],
+  ) -> Self {
+    Self {
+      key_source,
+      label,
+      fields: fields.iter().map(|&field| field.to_owned()).collect(),
+      block: [0u8; 32],
+      index: 0,
+      position: 32,
+    }
+  }
+}
+
+impl<'a> Iterator for Stream<'a> {
+  type Item = u8;
+
+  fn next(&mut self) -> Option<u8> {
+    if self.position == 32 {
+      let index = self.index.to_string();
+      let fields = self
+        .fields
+        .iter()
+        .map(String::as_str)
+        .chain([index.as_str()])
+        .collect::<Vec<_>>();
+      self.block = self.key_source.derive(self.label, &fields);
+      self.index += 1;
+      self.position = 0;
+    }
+    self.position += 1;
+    Some(self.block[self.position - 1])
+  }
+}
+
+impl<'a> Drop for Stream<'a> {
+  fn drop(&mut self) {
+    self.block = [0u8; 32];
+  }
+}
@@ -2,6 +2,13 @@
 
 use std::mem;
 
+mod bits;
+pub mod pin;
+mod policy;
+pub mod words;
+
+pub use policy::{ParsePolicyError, Policy};
+
 pub const PREPARED_SKELETON_KEY_SIZE: usize = 32;
 
 // XXX(soija) TODO: Introduce a proper error type instead of a unit.
@@ -19,6 +26,9 @@ pub fn prepare_skeleton_key(
   }
 }
 
+/// The size of the keys yielded by `KeyIter` in bytes.
+pub const KEY_SIZE: usize = 10;
+
 pub struct KeySource {
   sk: [u8; 32],
 }
@@ -35,6 +45,27 @@ const FINGERPRINT_SALT: [u8; 32] = [
   0xAA, 0xD7, 0x9F, 0xC5, 0x51, 0x20,
 ];
 
+/// The size of the TOTP seed in bytes.  This is the 160 bits recommended by
+/// RFC 4226.
+pub const TOTP_SEED_SIZE: usize = 20;
+
+const TOTP_SEED_LABEL: &str = "skele/totp-seed/v1";
+
+/// The size of the Ed25519 seed, i.e. the secret key of RFC 8032, in bytes.
+pub const ED25519_SEED_SIZE: usize = 32;
+
+const ED25519_SEED_LABEL: &str = "skele/ed25519-seed/v1";
+
+/// The most key material `KeySource::fill_bytes` gives, that is, the 255
+/// blocks of HKDF-Expand.
+pub const MAX_FILL_SIZE: usize = 255 * 32;
+
+/// The number of words in the answers to security questions.  Four words of
+/// the BIP 39 list carry 44 bits, plenty against guessing through a web form.
+pub const SECURITY_ANSWER_WORDS: usize = 4;
+
+const SECURITY_ANSWER_LABEL: &str = "skele/security-answer/v1";
+
 impl KeySource {
   pub fn new(key: &str) -> Self {
     let mut key_source = KeySource { sk: [0u8; 32] };
@@ -42,10 +73,110 @@ impl KeySource {
     key_source
   }
 
-  pub fn keys(&self, domain: &str, identity: &str) -> KeyIter {
+  pub fn keys(&self, domain: &str, identity: &str) -> KeyIter<'_> {
     KeyIter::new(&self.sk, domain, identity)
   }
 
+  /// Returns the seed of the time-based one-time passwords (RFC 6238) of the
+  /// domain-identity pair.
+  pub fn totp_seed(
+    &self,
+    domain: &str,
+    identity: &str,
+  ) -> [u8; TOTP_SEED_SIZE] {
+    let mut seed = [0u8; TOTP_SEED_SIZE];
+    seed.copy_from_slice(
+      &self.derive(TOTP_SEED_LABEL, &[domain, identity])[..TOTP_SEED_SIZE],
+    );
+    seed
+  }
+
+  /// Returns the seed of the Ed25519 key pair of the domain-identity pair.
+  /// The `purpose` tells apart the keys of the same pair, e.g. the ones for
+  /// logging in and for signing commits.
+  pub fn ed25519_seed(
+    &self,
+    domain: &str,
+    identity: &str,
+    purpose: &str,
+  ) -> [u8; ED25519_SEED_SIZE] {
+    self.derive(ED25519_SEED_LABEL, &[domain, identity, purpose])
+  }
+
+  /// Returns the answer to the security question of the domain-identity
+  /// pair.  The answer is a few lowercase words separated by spaces.  The
+  /// question is normalized (see [`normalize_question`]) so that small
+  /// differences in how sites phrase it do not change the answer.
+  pub fn security_answer(
+    &self,
+    domain: &str,
+    identity: &str,
+    question: &str,
+  ) -> String {
+    let seed = self.derive(
+      SECURITY_ANSWER_LABEL,
+      &[domain, identity, &normalize_question(question)],
+    );
+    words::pick(
+      &mut bits::Bits::new(seed.into_iter()),
+      words::WordList::Bip39English.words(),
+      SECURITY_ANSWER_WORDS,
+      ' ',
+    )
+  }
+
+  /// Fills `output` with key material for the purpose named by `label`
+  /// derived from the given fields.  Unlike the keys, which are cut to
+  /// `KEY_SIZE` bytes, the output can be of any length up to the limit of
+  /// HKDF-Expand (RFC 5869) that stretches the outcome of `derive`.  Shorter
+  /// outputs are prefixes of longer ones.
+  ///
+  /// HKDF-Expand is written out here because `hmac_sha256::HKDF::expand`
+  /// stops one block short of the limit.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `output` is longer than [`MAX_FILL_SIZE`] bytes.
+  pub fn fill_bytes(&self, label: &str, fields: &[&str], output: &mut [u8]) {
+    assert!(output.len() <= MAX_FILL_SIZE, "too much key material");
+    let prk = self.derive(label, fields);
+    let mut block = [0u8; 32];
+    for (i, chunk) in output.chunks_mut(32).enumerate() {
+      let mut hmac = hmac_sha256::HMAC::new(prk);
+      if i != 0 {
+        hmac.update(block);
+      }
+      // The info is empty and the counter of the block starts from one.
+      hmac.update([i as u8 + 1]);
+      block = hmac.finalize();
+      chunk.copy_from_slice(&block[..chunk.len()]);
+    }
+  }
+
+  /// Returns an endless stream of key material for the purpose named by
+  /// `label` derived from the given fields, for drawing passwords with
+  /// rejection sampling that may need any number of bytes.
+  pub(crate) fn stream(
+    &self,
+    label: &'static str,
+    fields: &[&str],
+  ) -> bits::Stream<'_> {
+    bits::Stream::new(self, label, fields)
+  }
+
+  /// Derives key material for the purpose named by `label` from the given
+  /// fields.  Unlike the passwords, which hash the plain concatenation of the
+  /// domain and the identity, this is an HMAC keyed with the skeleton key over
+  /// length-prefixed fields so that no two purposes or field lists collide.
+  fn derive(&self, label: &str, fields: &[&str]) -> [u8; 32] {
+    let mut hmac = hmac_sha256::HMAC::new(self.sk);
+    for field in std::iter::once(&label).chain(fields) {
+      hmac.update((field.len() as u64).to_be_bytes());
+      hmac.update(field.as_bytes());
+    }
+    hmac.finalize()
+  }
+
   pub fn fingerprint(&self) -> Vec<u8> {
     let mut hasher = hmac_sha256::Hash::new();
     hasher.update(self.sk);
@@ -55,6 +186,20 @@ impl KeySource {
   }
 }
 
+/// Normalizes the text of a security question: lowercases it, drops the
+/// apostrophes, and turns every run of other characters than letters and
+/// digits into a single space.  For example, "What is your mother's maiden
+/// name?" becomes "what is your mothers maiden name".
+pub fn normalize_question(question: &str) -> String {
+  question
+    .to_lowercase()
+    .replace(['\'', '\u{2019}'], "")
+    .split(|c: char| !c.is_alphanumeric())
+    .filter(|word| !word.is_empty())
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
 impl Drop for KeySource {
   fn drop(&mut self) {
     self.sk = [0u8; 32];
@@ -97,7 +242,7 @@ impl<'a> Iterator for KeyIter<'a> {
     // case I could have implemented the algorithm myself). Now I'm stuck
     // with this choice.
     //
-    let item = self.hash[0..10].to_vec();
+    let item = self.hash[..KEY_SIZE].to_vec();
     let h = &mut self.hasher;
     h.update(self.sk);
     h.update(self.hash);
@@ -108,7 +253,7 @@ impl<'a> Iterator for KeyIter<'a> {
 
 pub mod base32 {
   fn output_size(input_size: usize) -> usize {
-    8 * ((input_size + 4) / 5)
+    8 * input_size.div_ceil(5)
   }
 
   #[allow(unused_parens)]
@@ -126,7 +271,7 @@ pub mod base32 {
   }
 
   pub fn encode(input: &[u8], output: &mut [u8]) {
-    assert!(input.len() % 5 == 0);
+    assert!(input.len().is_multiple_of(5));
     assert!(output.len() == output_size(input.len()));
     let n = input.len() / 5;
     for i in 0..n {
@@ -157,6 +302,53 @@ pub mod base32 {
     crockford(input, &mut buffer);
     String::from_utf8(buffer).unwrap()
   }
+
+  const RFC4648_TRANSLATION_TABLE: &[u8; 32] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+  /// Encodes the octets with the standard Base32 alphabet of RFC 4648.  The
+  /// number of octets must be a multiple of five so that no padding is needed.
+  pub fn to_rfc4648(octets: &[u8]) -> String {
+    let mut b32 = vec![0u8; output_size(octets.len())];
+    encode(octets, &mut b32);
+    b32
+      .iter()
+      .map(|&b| RFC4648_TRANSLATION_TABLE[b as usize] as char)
+      .collect()
+  }
+
+  /// Decodes text in the standard Base32 alphabet of RFC 4648.  The letters
+  /// may be in either case and the padding, spaces, and dashes that often
+  /// accompany secrets meant to be typed are ignored.  Returns `None` if the
+  /// text has other characters or its length is not a valid Base32 length.
+  pub fn from_rfc4648(text: &str) -> Option<Vec<u8>> {
+    let mut octets = Vec::with_capacity(text.len() * 5 / 8);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    let mut n = 0;
+    for c in text.bytes() {
+      let value = match c.to_ascii_uppercase() {
+        c @ b'A'..=b'Z' => c - b'A',
+        c @ b'2'..=b'7' => c - b'2' + 26,
+        b'=' | b' ' | b'-' => continue,
+        _ => return None,
+      };
+      buffer = (buffer << 5) | value as u32;
+      bits += 5;
+      n += 1;
+      if bits >= 8 {
+        bits -= 8;
+        octets.push((buffer >> bits) as u8);
+        buffer &= (1 << bits) - 1;
+      }
+    }
+    // A trailing group of 1, 3, or 6 characters cannot come from whole octets.
+    if matches!(n % 8, 1 | 3 | 6) {
+      None
+    } else {
+      Some(octets)
+    }
+  }
 }
 
 pub struct SplitGroups<'a> {
@@ -166,7 +358,7 @@ pub struct SplitGroups<'a> {
   finished: bool,
 }
 
-pub fn split_groups(string: &str, group_size: usize) -> SplitGroups {
+pub fn split_groups(string: &str, group_size: usize) -> SplitGroups<'_> {
   if !string.is_empty() && group_size > 0 {
     SplitGroups {
       string,
@@ -267,4 +459,102 @@ mod tests {
     let key_str_2 = to_canonical(&key_str);
     assert_eq!(key_str_2, "5Wsc-X2mz-Csnc-4Vgc");
   }
+
+  #[test]
+  fn test_totp_seed() {
+    let key_source = KeySource::new("secret skeleton passphrase");
+    let seed = key_source.totp_seed("domain", "identity");
+    assert_eq!(
+      base32::to_rfc4648(&seed),
+      "UXNBBZAQVV366SP27VN3F5PNHFPAJONX"
+    );
+    // Separate from the password and from the other pairs
+    assert_ne!(
+      &seed[..10],
+      &key_source.keys("domain", "identity").next().unwrap()[..]
+    );
+    assert_ne!(seed, key_source.totp_seed("domai", "nidentity"));
+  }
+
+  #[test]
+  fn test_ed25519_seed() {
+    let key_source = KeySource::new("secret skeleton passphrase");
+    let seed = key_source.ed25519_seed("github.com", "alice", "ssh");
+    assert_eq!(
+      seed,
+      [
+        0xC7, 0x55, 0x8A, 0xA2, 0x9B, 0x71, 0x7C, 0xFA, 0x44, 0xF1, 0x56, 0xB2,
+        0xAC, 0xD0, 0x67, 0xB6, 0x76, 0x2A, 0x84, 0xAD, 0xFA, 0x05, 0x31, 0x85,
+        0x21, 0xDF, 0x40, 0x79, 0x83, 0xF1, 0x77, 0x40,
+      ]
+    );
+    assert_ne!(seed, key_source.ed25519_seed("github.com", "alice", "git"));
+    assert_ne!(seed, key_source.ed25519_seed("github.com", "alicessh", ""));
+  }
+
+  #[test]
+  fn test_fill_bytes() {
+    let key_source = KeySource::new("secret skeleton passphrase");
+    let mut long = [0u8; 40];
+    key_source.fill_bytes("test", &["domain", "identity"], &mut long);
+    assert_eq!(
+      base32::to_rfc4648(&long),
+      "CTHDWYK2KDKTCEHFJWOCRIRHCZH6UN7KTVVVJHOM2VZYQCFL4EK3TXQJRJSGJILF"
+    );
+    let mut short = [0u8; 7];
+    key_source.fill_bytes("test", &["domain", "identity"], &mut short);
+    assert_eq!(short, long[..7]);
+    key_source.fill_bytes("test", &["domain", "identit"], &mut short);
+    assert_ne!(short, long[..7]);
+    let mut longest = [0u8; MAX_FILL_SIZE];
+    key_source.fill_bytes("test", &["domain", "identity"], &mut longest);
+    assert_eq!(longest[..40], long);
+  }
+
+  #[test]
+  #[should_panic(expected = "too much key material")]
+  fn test_fill_bytes_too_long() {
+    let key_source = KeySource::new("secret skeleton passphrase");
+    let mut output = [0u8; MAX_FILL_SIZE + 1];
+    key_source.fill_bytes("test", &["domain", "identity"], &mut output);
+  }
+
+  #[test]
+  fn test_security_answer() {
+    let key_source = KeySource::new("secret skeleton passphrase");
+    let question = "What is your mother's maiden name?";
+    assert_eq!(
+      normalize_question(question),
+      "what is your mothers maiden name"
+    );
+    assert_eq!(
+      key_source.security_answer("domain", "identity", question),
+      "suggest ice second swear"
+    );
+    assert_eq!(
+      key_source.security_answer(
+        "domain",
+        "identity",
+        "  WHAT is your mother\u{2019}s maiden-name "
+      ),
+      key_source.security_answer("domain", "identity", question)
+    );
+    assert_ne!(
+      key_source.security_answer("domain", "identity", "first pet"),
+      key_source.security_answer("domain", "identity", question)
+    );
+  }
+
+  #[test]
+  fn test_rfc4648() {
+    // RFC 4648 section 10
+    assert_eq!(base32::to_rfc4648(b"fooba"), "MZXW6YTB");
+    assert_eq!(base32::to_rfc4648(b""), "");
+    assert_eq!(base32::from_rfc4648("MZXW6YTB").unwrap(), b"fooba");
+    assert_eq!(base32::from_rfc4648("mzxw 6yq=").unwrap(), b"foob");
+    assert_eq!(base32::from_rfc4648("MZXW6===").unwrap(), b"foo");
+    assert_eq!(base32::from_rfc4648("MY").unwrap(), b"f");
+    assert!(base32::from_rfc4648("MZXW6YT1").is_none());
+    assert!(base32::from_rfc4648("MZX").is_none());
+  }
 }
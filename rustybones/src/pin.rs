@@ -0,0 +1,176 @@
+//! Numeric PINs.
+//!
+//! Every digit is drawn with rejection sampling so that all PINs of the given
+//! length are equally likely.  Optionally the PINs tried first when guessing
+//! are skipped by drawing again, which keeps the PIN deterministic.
+
+use crate::bits::Bits;
+
+/// Returns a PIN of the given number of digits drawn from the bytes, which
+/// must not run out.  With `skip_weak` the PINs for which [`is_weak`] holds
+/// are skipped.
+///
+/// # Panics
+///
+/// Panics if `skip_weak` is set and there are fewer than three digits, as
+/// then every PIN is weak and none could ever be returned.
+pub fn pin(
+  bytes: impl Iterator<Item = u8>,
+  digits: usize,
+  skip_weak: bool,
+) -> String {
+  assert!(!skip_weak || digits >= 3, "every PIN this short is weak");
+  let mut bits = Bits::new(bytes);
+  loop {
+    let pin = (0..digits)
+      .map(|_| char::from(b'0' + bits.uniform(10) as u8))
+      .collect::<String>();
+    if !(skip_weak && is_weak(&pin)) {
+      return pin;
+    }
+  }
+}
+
+/// Tells whether the PIN follows a pattern that makes it among the first ones
+/// to be guessed:
+///
+/// - repeats of one or two digits such as `0000` and `1212`,
+/// - ascending and descending runs such as `1234` and `8765`,
+/// - years from 1900 to 2039 such as `1987`, and
+/// - dates such as `2403` and `03241987` in the usual day, month, and year
+///   orders.
+pub fn is_weak(pin: &str) -> bool {
+  let digits = pin.bytes().map(|b| (b - b'0') as i32).collect::<Vec<_>>();
+  let repeats = |period: usize| {
+    (period..digits.len()).all(|i| digits[i] == digits[i - period])
+  };
+  let run = |step: i32| digits.windows(2).all(|w| w[1] - w[0] == step);
+  repeats(1) || repeats(2) || run(1) || run(-1) || is_date(&digits)
+}
+
+/// Returns the number of PINs of the given number of digits for which
+/// [`is_weak`] holds.  The PINs that follow a pattern are few enough to be
+/// listed and counted one by one.
+pub fn weak_count(digits: usize) -> usize {
+  let mut candidates = Vec::new();
+  // Repeats of one or two digits.
+  for pair in 0..100 {
+    let pair = format!("{:02}", pair);
+    candidates.push(pair.repeat(digits.div_ceil(2))[..digits].to_owned());
+  }
+  // Ascending and descending runs.
+  for start in 0..10 {
+    for step in [-1, 1] {
+      let run = (0..digits as i32)
+        .map(|i| start + step * i)
+        .map(|d| char::from_digit(d.try_into().ok()?, 10))
+        .collect::<Option<String>>();
+      candidates.extend(run);
+    }
+  }
+  // Dates in any order and years; the ones that are not valid are weeded
+  // out below.
+  let years = match digits {
+    4 | 8 => (1900..=2039).map(|y| format!("{:04}", y)).collect(),
+    6 => (0..100).map(|y| format!("{:02}", y)).collect(),
+    _ => Vec::new(),
+  };
+  for day in (1..=31).map(|d| format!("{:02}", d)) {
+    for month in (1..=12).map(|m| format!("{:02}", m)) {
+      candidates.push(format!("{}{}", day, month));
+      candidates.push(format!("{}{}", month, day));
+      for year in years.iter() {
+        candidates.push(format!("{}{}{}", day, month, year));
+        candidates.push(format!("{}{}{}", month, day, year));
+        candidates.push(format!("{}{}{}", year, month, day));
+      }
+    }
+  }
+  candidates.extend(years);
+  candidates.retain(|pin| pin.len() == digits && is_weak(pin));
+  candidates.sort();
+  candidates.dedup();
+  candidates.len()
+}
+
+fn is_date(digits: &[i32]) -> bool {
+  let number = |range: std::ops::Range<usize>| {
+    digits[range].iter().fold(0, |n, d| 10 * n + d)
+  };
+  let day = |n: i32| (1..=31).contains(&n);
+  let month = |n: i32| (1..=12).contains(&n);
+  let year = |n: i32| (1900..=2039).contains(&n);
+  match digits.len() {
+    // YYYY, DDMM, MMDD
+    4 => {
+      year(number(0..4))
+        || day(number(0..2)) && month(number(2..4))
+        || month(number(0..2)) && day(number(2..4))
+    }
+    // DDMMYY, MMDDYY, YYMMDD
+    6 => {
+      day(number(0..2)) && month(number(2..4))
+        || month(number(0..2)) && day(number(2..4))
+        || month(number(2..4)) && day(number(4..6))
+    }
+    // DDMMYYYY, MMDDYYYY, YYYYMMDD
+    8 => {
+      year(number(4..8))
+        && (day(number(0..2)) && month(number(2..4))
+          || month(number(0..2)) && day(number(2..4)))
+        || year(number(0..4)) && month(number(4..6)) && day(number(6..8))
+    }
+    _ => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+  use crate::KeySource;
+
+  #[test]
+  fn test_pin() {
+    let key_source = KeySource::new("secret skeleton passphrase");
+    let bytes = || key_source.stream("test", &["domain", "identity"]);
+    assert_eq!(pin(bytes(), 4, false), "0858");
+    assert_eq!(pin(bytes(), 12, false), "085819764183");
+    // The first PIN drawn is a date and gets skipped.
+    let bytes = || key_source.stream("test", &["domain", "id10"]);
+    assert_eq!(pin(bytes(), 4, false), "0814");
+    assert_eq!(pin(bytes(), 4, true), "2259");
+  }
+
+  #[test]
+  #[should_panic(expected = "every PIN this short is weak")]
+  fn test_pin_too_short_to_skip_weak() {
+    let key_source = KeySource::new("secret skeleton passphrase");
+    pin(key_source.stream("test", &["domain", "identity"]), 2, true);
+  }
+
+  #[test]
+  fn test_is_weak() {
+    for weak in [
+      "0000", "1212", "1234", "9876", "1987", "2024", "2403", "1224", "121212",
+      "240387", "123456", "24031987", "19870324", "03241987",
+    ] {
+      assert!(is_weak(weak), "{}", weak);
+    }
+    for strong in ["7391", "1357", "5839", "3200", "982641", "19871324"] {
+      assert!(!is_weak(strong), "{}", strong);
+    }
+  }
+
+  #[test]
+  fn test_weak_count() {
+    for digits in 4..=6 {
+      let weak = (0..10usize.pow(digits as u32))
+        .filter(|n| is_weak(&format!("{:0width$}", n, width = digits)))
+        .count();
+      assert_eq!(weak_count(digits), weak, "{}", digits);
+    }
+    // Only the repeats are this long.
+    assert_eq!(weak_count(12), 100);
+  }
+}
@@ -0,0 +1,361 @@
+//! Password policies, that is, how the keys of a domain-identity pair are
+//! turned into a password.
+
+use std::{fmt, str::FromStr};
+
+use crate::{
+  base32, format_key, pin, to_canonical,
+  words::{self, WordList},
+  KeySource,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+  /// Crockford Base32 in groups of four, e.g. `5Wsc-X2mz-Csnc-4Vgc`.  The
+  /// passwords of the default length are the original keys; the longer and
+  /// shorter ones are cut from key material of any length (see
+  /// `KeySource::fill_bytes`).
+  Base32 { length: usize },
+  /// Words from a word list, e.g.
+  /// `february-load-garbage-unable-point-tomorrow-main`, for passwords that
+  /// have to be typed or spoken.  Like the `Pin` passwords they are drawn
+  /// from a stream of key material of their own for each generation (see
+  /// `KeySource::stream`), so no two generations share any of it.
+  Words {
+    list: WordList,
+    count: usize,
+    separator: char,
+  },
+  /// Decimal digits, e.g. `1061`, for bank cards, phones, and doors.  With
+  /// `skip_weak` the PINs that are easy to guess are never picked.
+  Pin { digits: usize, skip_weak: bool },
+}
+
+/// The bounds and the default of the length of the `Base32` policy in
+/// characters.  The length has to be a multiple of the group size.
+const MIN_BASE32_LENGTH: usize = 12;
+const MAX_BASE32_LENGTH: usize = 64;
+const DEFAULT_BASE32_LENGTH: usize = 16;
+const BASE32_GROUP_SIZE: usize = 4;
+
+/// Names the key material of the `Base32` passwords that are not of the
+/// default length.
+const BASE32_LABEL: &str = "skele/base32-password/v1";
+
+/// Names the key material of the `Words` and the `Pin` passwords.
+const WORDS_LABEL: &str = "skele/words-password/v1";
+const PIN_LABEL: &str = "skele/pin-password/v1";
+
+/// The bounds of the word count of the `Words` policy.  The words are drawn
+/// from an endless stream, so the upper bound only keeps the passphrases
+/// typeable: sixteen words of the BIP 39 list carry 176 bits already.
+const MIN_WORDS: usize = 4;
+const MAX_WORDS: usize = 16;
+
+/// The defaults of the `Words` policy.  Seven words of the BIP 39 list carry
+/// 77 bits, about as much as the 80 bits of the `Base32` policy.
+const DEFAULT_WORDS: usize = 7;
+const DEFAULT_SEPARATOR: char = '-';
+
+/// The bounds and the default of the length of the `Pin` policy.
+const MIN_DIGITS: usize = 4;
+const MAX_DIGITS: usize = 12;
+const DEFAULT_DIGITS: usize = 4;
+
+const SKIP_WEAK: &str = "skip-weak";
+
+impl Default for Policy {
+  fn default() -> Self {
+    Policy::Base32 {
+      length: DEFAULT_BASE32_LENGTH,
+    }
+  }
+}
+
+impl Policy {
+  /// Returns the password of the given `generation` of the domain-identity
+  /// pair.
+  pub fn password(
+    &self,
+    key_source: &KeySource,
+    domain: &str,
+    identity: &str,
+    generation: usize,
+  ) -> String {
+    match self {
+      Policy::Base32 {
+        length: DEFAULT_BASE32_LENGTH,
+      } => {
+        let key = key_source.keys(domain, identity).nth(generation);
+        format_key(&key.expect("key iterator is endless"), BASE32_GROUP_SIZE)
+      }
+      Policy::Base32 { length } => {
+        // Whole blocks of five octets, i.e. eight characters, are encoded and
+        // the surplus characters dropped.
+        let mut octets = vec![0u8; length.div_ceil(8) * 5];
+        key_source.fill_bytes(
+          BASE32_LABEL,
+          &[domain, identity, &generation.to_string()],
+          &mut octets,
+        );
+        let mut b32 = vec![0u8; octets.len() / 5 * 8];
+        base32::encode(&octets, &mut b32);
+        octets.fill(0);
+        let password = to_canonical(&base32::to_crockford(&b32[..*length]));
+        b32.fill(0);
+        password
+      }
+      Policy::Words {
+        list,
+        count,
+        separator,
+      } => words::passphrase(
+        key_source
+          .stream(WORDS_LABEL, &[domain, identity, &generation.to_string()]),
+        *list,
+        *count,
+        *separator,
+      ),
+      Policy::Pin { digits, skip_weak } => pin::pin(
+        key_source
+          .stream(PIN_LABEL, &[domain, identity, &generation.to_string()]),
+        *digits,
+        *skip_weak,
+      ),
+    }
+  }
+
+  /// Returns the entropy of the passwords in bits, i.e. the base 2 logarithm
+  /// of the number of passwords the policy picks from.  Every password is
+  /// equally likely, and the letter case and the separators follow from the
+  /// policy, so that is all there is to guess.
+  pub fn entropy(&self) -> f64 {
+    match self {
+      Policy::Base32 { length } => (5 * length) as f64,
+      Policy::Words { list, count, .. } => {
+        *count as f64 * (list.words().len() as f64).log2()
+      }
+      Policy::Pin { digits, skip_weak } => {
+        let weak = if *skip_weak {
+          pin::weak_count(*digits)
+        } else {
+          0
+        };
+        (10f64.powi(*digits as i32) - weak as f64).log2()
+      }
+    }
+  }
+}
+
+impl fmt::Display for Policy {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Policy::Base32 {
+        length: DEFAULT_BASE32_LENGTH,
+      } => f.write_str("base32"),
+      Policy::Base32 { length } => write!(f, "base32:{}", length),
+      Policy::Words {
+        list,
+        count,
+        separator,
+      } => write!(f, "words:{}:{}:{}", list.name(), count, separator),
+      Policy::Pin {
+        digits,
+        skip_weak: false,
+      } => write!(f, "pin:{}", digits),
+      Policy::Pin {
+        digits,
+        skip_weak: true,
+      } => write!(f, "pin:{}:{}", digits, SKIP_WEAK),
+    }
+  }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParsePolicyError;
+
+impl fmt::Display for ParsePolicyError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("unknown password policy")
+  }
+}
+
+impl std::error::Error for ParsePolicyError {}
+
+impl FromStr for Policy {
+  type Err = ParsePolicyError;
+
+  /// Parses the policy from the form given by `Display`.  The parameters of
+  /// the policies may be left out from the end, e.g. `base32`, `words`, or
+  /// `words:bip39:5`, in which case they take their defaults.
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut parts = s.splitn(4, ':');
+    match parts.next() {
+      Some("base32") => {
+        let length = match parts.next() {
+          Some(length) => length
+            .parse()
+            .ok()
+            .filter(|length| {
+              (MIN_BASE32_LENGTH..=MAX_BASE32_LENGTH).contains(length)
+                && length % BASE32_GROUP_SIZE == 0
+            })
+            .ok_or(ParsePolicyError)?,
+          None => DEFAULT_BASE32_LENGTH,
+        };
+        if parts.next().is_some() {
+          return Err(ParsePolicyError);
+        }
+        Ok(Policy::Base32 { length })
+      }
+      Some("words") => {
+        let list = match parts.next() {
+          Some(name) => WordList::from_name(name).ok_or(ParsePolicyError)?,
+          None => WordList::default(),
+        };
+        let count = match parts.next() {
+          Some(count) => count
+            .parse()
+            .ok()
+            .filter(|count| (MIN_WORDS..=MAX_WORDS).contains(count))
+            .ok_or(ParsePolicyError)?,
+          None => DEFAULT_WORDS,
+        };
+        let separator = match parts.next() {
+          Some(separator) => {
+            let mut chars = separator.chars();
+            match (chars.next(), chars.next()) {
+              (Some(c), None) => c,
+              _ => return Err(ParsePolicyError),
+            }
+          }
+          None => DEFAULT_SEPARATOR,
+        };
+        Ok(Policy::Words {
+          list,
+          count,
+          separator,
+        })
+      }
+      Some("pin") => {
+        let digits = match parts.next() {
+          Some(digits) => digits
+            .parse()
+            .ok()
+            .filter(|digits| (MIN_DIGITS..=MAX_DIGITS).contains(digits))
+            .ok_or(ParsePolicyError)?,
+          None => DEFAULT_DIGITS,
+        };
+        let skip_weak = match parts.next() {
+          Some(SKIP_WEAK) => true,
+          Some(_) => return Err(ParsePolicyError),
+          None => false,
+        };
+        if parts.next().is_some() {
+          return Err(ParsePolicyError);
+        }
+        Ok(Policy::Pin { digits, skip_weak })
+      }
+      _ => Err(ParsePolicyError),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn test_base32_policy() {
+    let key_source = KeySource::new("secret skeleton passphrase");
+    let policy = "base32".parse::<Policy>().unwrap();
+    assert_eq!(policy.to_string(), "base32");
+    assert_eq!(
+      policy.password(&key_source, "domain", "identity", 0),
+      "5Wsc-X2mz-Csnc-4Vgc"
+    );
+    assert_eq!("base32:16".parse::<Policy>(), Ok(policy));
+    let policy = "base32:32".parse::<Policy>().unwrap();
+    assert_eq!(policy.to_string(), "base32:32");
+    let password = policy.password(&key_source, "domain", "identity", 0);
+    assert_eq!(password, "7P3d-Kery-B16n-Mz32-Cb2n-Paxd-F5ag-Zcvn");
+    // Shorter passwords are prefixes of the longer ones.
+    assert_eq!(
+      "base32:20".parse::<Policy>().unwrap().password(
+        &key_source,
+        "domain",
+        "identity",
+        0
+      ),
+      password[..24]
+    );
+    assert_eq!(
+      "base32:24".parse::<Policy>().unwrap().password(
+        &key_source,
+        "domain",
+        "identity",
+        1
+      ),
+      "8Qm5-7Qen-Ss84-Dp4e-9568-Tdhd"
+    );
+    for invalid in ["base32:8", "base32:18", "base32:68", "base32:32:x"] {
+      assert_eq!(invalid.parse::<Policy>(), Err(ParsePolicyError));
+    }
+  }
+
+  #[test]
+  fn test_words_policy() {
+    let key_source = KeySource::new("secret skeleton passphrase");
+    let policy = "words".parse::<Policy>().unwrap();
+    assert_eq!(policy.to_string(), "words:bip39:7:-");
+    assert_eq!(
+      policy.password(&key_source, "domain", "identity", 0),
+      "february-load-garbage-unable-point-tomorrow-main"
+    );
+    assert_eq!(
+      "words:bip39:4: ".parse::<Policy>().unwrap().password(
+        &key_source,
+        "domain",
+        "identity",
+        1
+      ),
+      "spatial weather good message"
+    );
+    for invalid in
+      ["words:eff", "words:bip39:3", "words:bip39:6:--", "base32:x"]
+    {
+      assert_eq!(invalid.parse::<Policy>(), Err(ParsePolicyError));
+    }
+  }
+
+  #[test]
+  fn test_pin_policy() {
+    let key_source = KeySource::new("secret skeleton passphrase");
+    let policy = "pin".parse::<Policy>().unwrap();
+    assert_eq!(policy.to_string(), "pin:4");
+    assert_eq!(
+      policy.password(&key_source, "domain", "identity", 0),
+      "1061"
+    );
+    let policy = "pin:4:skip-weak".parse::<Policy>().unwrap();
+    assert_eq!(policy.to_string(), "pin:4:skip-weak");
+    assert_eq!(policy.password(&key_source, "domain", "id9", 0), "7589");
+    for invalid in ["pin:3", "pin:13", "pin:4:weak", "pin:4:skip-weak:x"] {
+      assert_eq!(invalid.parse::<Policy>(), Err(ParsePolicyError));
+    }
+  }
+
+  #[test]
+  fn test_entropy() {
+    let entropy = |policy: &str| policy.parse::<Policy>().unwrap().entropy();
+    assert_eq!(entropy("base32"), 80.0);
+    assert_eq!(entropy("base32:12"), 60.0);
+    assert_eq!(entropy("words"), 77.0);
+    assert_eq!(entropy("words:bip39:4"), 44.0);
+    assert_eq!(entropy("pin:6"), 1e6f64.log2());
+    // Skipping the weak PINs takes away some of the few there are.
+    assert!(entropy("pin:4:skip-weak") < entropy("pin:4") - 0.1);
+    assert!(entropy("pin:4:skip-weak") > 13.0);
+  }
+}
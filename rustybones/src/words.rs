@@ -0,0 +1,113 @@
+//! Passphrases made of words picked from a word list.
+//!
+//! The words are picked with rejection sampling so that every word of the list
+//! is equally likely.
+
+use std::sync::OnceLock;
+
+use crate::bits::Bits;
+
+/// The English word list of BIP 39.  The SHA-256 of the file is
+/// `2f5eed53a4727b4bf8880d8f3f199efc90e58503646d9ff8eff3a2ed3b24dbda`.
+const BIP39_ENGLISH: &str = include_str!("wordlists/bip39_english.txt");
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WordList {
+  /// The 2048 English words of BIP 39.  No word is a prefix of another and
+  /// the first four letters tell the words apart.
+  #[default]
+  Bip39English,
+}
+
+impl WordList {
+  pub fn name(&self) -> &'static str {
+    match self {
+      WordList::Bip39English => "bip39",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "bip39" => Some(WordList::Bip39English),
+      _ => None,
+    }
+  }
+
+  pub fn words(&self) -> &'static [&'static str] {
+    static BIP39: OnceLock<Vec<&'static str>> = OnceLock::new();
+    match self {
+      WordList::Bip39English => {
+        BIP39.get_or_init(|| BIP39_ENGLISH.lines().collect())
+      }
+    }
+  }
+}
+
+/// Returns `count` words from the list joined with the separator.  The words
+/// are drawn from the bytes, which must not run out.
+pub fn passphrase(
+  bytes: impl Iterator<Item = u8>,
+  list: WordList,
+  count: usize,
+  separator: char,
+) -> String {
+  pick(&mut Bits::new(bytes), list.words(), count, separator)
+}
+
+/// Returns `count` words drawn from the bits joined with the separator.
+pub(crate) fn pick<I: Iterator<Item = u8>>(
+  bits: &mut Bits<I>,
+  words: &[&str],
+  count: usize,
+  separator: char,
+) -> String {
+  let mut passphrase = String::new();
+  for i in 0..count {
+    let index = bits.uniform(words.len());
+    if i > 0 {
+      passphrase.push(separator);
+    }
+    passphrase.push_str(words[index]);
+  }
+  passphrase
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+  use crate::KeySource;
+
+  #[test]
+  fn test_bip39_english() {
+    let words = WordList::Bip39English.words();
+    assert_eq!(words.len(), 2048);
+    assert_eq!((words[0], words[2047]), ("abandon", "zoo"));
+  }
+
+  #[test]
+  fn test_passphrase() {
+    let key_source = KeySource::new("secret skeleton passphrase");
+    let bytes = key_source.stream("test", &["domain", "identity"]);
+    assert_eq!(
+      passphrase(bytes, WordList::Bip39English, 7, '-'),
+      "artefact-drift-debris-depend-rent-army-under"
+    );
+    let bytes = key_source.stream("test", &["domain", "identity", "1"]);
+    assert_eq!(
+      passphrase(bytes, WordList::Bip39English, 7, ' '),
+      "future hollow just calm horror void record"
+    );
+  }
+
+  #[test]
+  fn test_rejection_sampling() {
+    // Two bits per word of which the value 3 is rejected
+    let key_source = KeySource::new("secret skeleton passphrase");
+    let bytes = key_source.stream("test", &["domain", "identity"]);
+    assert_eq!(
+      pick(&mut Bits::new(bytes), &["a", "b", "c"], 12, ' '),
+      "a a a c c a b b c a c a"
+    );
+  }
+}
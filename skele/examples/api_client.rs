@@ -0,0 +1,102 @@
+//! A minimal client for the local API served by `skele api`.
+//!
+//! ```sh
+//! cargo run --example api_client -- list
+//! cargo run --example api_client -- get example.com alice [GENERATION]
+//! cargo run --example api_client -- touch example.com alice
+//! ```
+//!
+//! The socket is looked up like `skele` does it: from `SKELE_API_SOCKET` or
+//! the default location.  The session token is read from the file next to the
+//! socket.
+
+use std::{
+  env, fs,
+  io::{BufRead, BufReader, Write},
+  os::unix::net::UnixStream,
+  path::PathBuf,
+  process,
+};
+
+use serde_json::{json, Value};
+
+fn socket_path() -> PathBuf {
+  if let Some(path) = env::var_os("SKELE_API_SOCKET") {
+    return PathBuf::from(path);
+  }
+  match env::var_os("XDG_RUNTIME_DIR") {
+    Some(dir) if !dir.is_empty() => {
+      PathBuf::from(dir).join("skele").join("api.sock")
+    }
+    _ => {
+      eprintln!("set SKELE_API_SOCKET to the path of the API socket");
+      process::exit(2);
+    }
+  }
+}
+
+fn usage() -> ! {
+  eprintln!(
+    "usage: api_client list | get DOMAIN IDENTITY [GENERATION] | touch DOMAIN \
+     IDENTITY"
+  );
+  process::exit(2);
+}
+
+fn main() {
+  let args = env::args().skip(1).collect::<Vec<_>>();
+  let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+  let socket_path = socket_path();
+  let token = fs::read_to_string(socket_path.with_extension("token"))
+    .unwrap_or_else(|err| {
+      eprintln!("cannot read the session token: {}", err);
+      process::exit(1);
+    });
+  let (method, mut params) = match args.as_slice() {
+    ["list"] => ("list", json!({})),
+    ["get", domain, identity] => {
+      ("get", json!({ "domain": domain, "identity": identity }))
+    }
+    ["get", domain, identity, generation] => {
+      let Ok(generation) = generation.parse::<usize>() else {
+        usage();
+      };
+      (
+        "get",
+        json!({
+          "domain": domain,
+          "identity": identity,
+          "generation": generation,
+        }),
+      )
+    }
+    ["touch", domain, identity] => {
+      ("touch", json!({ "domain": domain, "identity": identity }))
+    }
+    _ => usage(),
+  };
+  params["token"] = Value::String(token.trim().to_owned());
+  let request = json!({
+    "jsonrpc": "2.0",
+    "id": 1,
+    "method": method,
+    "params": params,
+  });
+
+  let mut stream = UnixStream::connect(&socket_path).unwrap_or_else(|err| {
+    eprintln!("cannot connect to {}: {}", socket_path.display(), err);
+    process::exit(1);
+  });
+  writeln!(stream, "{}", request).unwrap();
+  let mut line = String::new();
+  BufReader::new(stream).read_line(&mut line).unwrap();
+  let reply: Value = serde_json::from_str(&line).unwrap();
+  if let Some(error) = reply.get("error") {
+    eprintln!("error {}: {}", error["code"], error["message"]);
+    process::exit(1);
+  }
+  println!(
+    "{}",
+    serde_json::to_string_pretty(&reply["result"]).unwrap()
+  );
+}
@@ -0,0 +1,469 @@
+//! # Key agent
+//!
+//! The agent unlocks a skeleton key once and then serves passwords to other
+//! skele invocations over a Unix domain socket.  The socket lives in a
+//! directory owned and accessible by the owner only, which the agent checks
+//! and tightens every time it starts, and the socket itself is created
+//! readable and writable by the owner only.  On Linux the agent also hangs up
+//! on clients running as another user.  The agent locks itself, that is
+//! forgets the key and exits, after being idle for a while or when asked to
+//! with `skele lock`.
+//!
+//! ## Protocol
+//!
+//! The client writes requests to the socket as JSON objects, one per line.  The
+//! agent answers each request with a JSON object on a single line.  A client
+//! may send any number of requests over a single connection.
+//!
+//! - `{"op":"status"}` is answered with
+//!   `{"result":"status","fingerprint":"…","idle_timeout":900}` where the
+//!   timeout is in seconds.
+//! - `{"op":"list"}` is answered with
+//!   `{"result":"list","credentials":[{"domain":"…","identity":"…"}]}` listing
+//!   the known credentials from the most to the least used.
+//! - `{"op":"get","domain":"…","identity":"…","generation":0}` is answered with
+//!   `{"result":"password","password":"…","generation":0,"policy":"base32"}`.
+//! - `{"op":"lock"}` is answered with `{"result":"locked"}` after which the
+//!   agent locks itself.
+//!
+//! A successful `get` counts as a use of the credentials.  Any request may be
+//! answered with `{"result":"error","message":"…"}`.
+//!
+//! ## Generations
+//!
+//! The `generation` of `get` is optional.  Without it the agent picks the
+//! generation in use for the credentials, so a client keeps getting the
+//! current password after the credentials have been rotated; the `generation`
+//! of the answer tells which one it was.  Clients that want the original
+//! password ask for generation 0 explicitly.  A `generation` above
+//! [`state::MAX_GENERATION`] is refused (see
+//! [`state::KeyState::requested_generation`]).
+//!
+//! The agent reads the key state anew for each request and saves the uses
+//! under the lock of the state file, so it sees the changes other skele
+//! invocations make while it runs and never overwrites them.
+
+use std::{
+  fs,
+  io::{self, BufRead, BufReader, Write},
+  os::unix::{
+    fs::{DirBuilderExt, MetadataExt, PermissionsExt},
+    net::{UnixListener, UnixStream},
+  },
+  path::{Path, PathBuf},
+  sync::mpsc::{self, RecvTimeoutError},
+  thread,
+  time::{Duration, Instant},
+};
+
+use rustybones::Policy;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+  serde_helpers::{de_from_str, ser_display},
+  state, KeyContainer,
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("i/o error")]
+  Io(#[from] io::Error),
+  #[error("agent is already running")]
+  AlreadyRunning,
+  #[error("agent is not running")]
+  NotRunning,
+  #[error("malformed agent message")]
+  MalformedMessage,
+  #[error("agent refused the request: {0}")]
+  Refused(String),
+  #[error("the socket directory {0} is not private to the user")]
+  InsecureDir(PathBuf),
+  #[error("failed to update key state")]
+  State(#[from] state::Error),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Request {
+  Status,
+  List,
+  Get {
+    domain: String,
+    identity: String,
+    #[serde(default)]
+    generation: Option<usize>,
+  },
+  Lock,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum Response {
+  Status {
+    fingerprint: String,
+    idle_timeout: u64,
+  },
+  List {
+    credentials: Vec<Entry>,
+  },
+  Password {
+    password: String,
+    #[serde(default)]
+    generation: usize,
+    #[serde(
+      default,
+      serialize_with = "ser_display",
+      deserialize_with = "de_from_str"
+    )]
+    policy: Policy,
+  },
+  Locked,
+  Error {
+    message: String,
+  },
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Entry {
+  pub domain: String,
+  pub identity: String,
+}
+
+/// How long the agent waits for a connected client to send a request.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns the default path of the agent socket.
+pub fn default_socket_path() -> Result<PathBuf, state::Error> {
+  Ok(state::runtime_dir()?.join("agent.sock"))
+}
+
+/// Binds the socket making sure that only the owner can access it.
+pub fn bind(path: &Path) -> Result<UnixListener, Error> {
+  if let Some(dir) = path.parent() {
+    ensure_private_dir(dir)?;
+  }
+  if path.exists() {
+    if UnixStream::connect(path).is_ok() {
+      return Err(Error::AlreadyRunning);
+    }
+    // A stale socket left behind by an agent that did not exit cleanly.
+    fs::remove_file(path)?;
+  }
+  // Others cannot connect even for the moment between creating the socket
+  // and setting its permissions.
+  let umask = rustix::process::umask(rustix::fs::Mode::from_raw_mode(0o177));
+  let listener = UnixListener::bind(path);
+  rustix::process::umask(umask);
+  let listener = listener?;
+  fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+  Ok(listener)
+}
+
+/// Creates the directory if needed and makes sure that it is owned by the
+/// user and accessible by the user only.
+fn ensure_private_dir(dir: &Path) -> Result<(), Error> {
+  fs::DirBuilder::new()
+    .recursive(true)
+    .mode(0o700)
+    .create(dir)?;
+  let metadata = fs::symlink_metadata(dir)?;
+  if !metadata.is_dir() || metadata.uid() != rustix::process::getuid().as_raw()
+  {
+    return Err(Error::InsecureDir(dir.to_owned()));
+  }
+  if metadata.mode() & 0o077 != 0 {
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+  }
+  Ok(())
+}
+
+/// Tells whether the client runs as the same user as the agent.  Only Linux
+/// tells who is at the other end of a socket; elsewhere the private
+/// directory alone keeps other users out.
+#[cfg(target_os = "linux")]
+fn is_own_client(stream: &UnixStream) -> bool {
+  rustix::net::sockopt::get_socket_peercred(stream)
+    .is_ok_and(|cred| cred.uid == rustix::process::getuid())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_own_client(_stream: &UnixStream) -> bool {
+  true
+}
+
+pub struct Agent<'a> {
+  app_state: &'a state::AppState,
+  key_container: KeyContainer,
+  idle_timeout: Duration,
+}
+
+impl<'a> Agent<'a> {
+  pub fn new(
+    app_state: &'a state::AppState,
+    key_container: KeyContainer,
+    idle_timeout: Duration,
+  ) -> Self {
+    Self {
+      app_state,
+      key_container,
+      idle_timeout,
+    }
+  }
+
+  /// Serves the clients until locked.
+  pub fn serve(mut self, path: &Path) -> Result<(), Error> {
+    let listener = bind(path)?;
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+      for stream in listener.incoming() {
+        if tx.send(stream).is_err() {
+          break;
+        }
+      }
+    });
+    let mut deadline = Instant::now() + self.idle_timeout;
+    let result = loop {
+      let timeout = deadline.saturating_duration_since(Instant::now());
+      match rx.recv_timeout(timeout) {
+        Ok(Ok(stream)) if !is_own_client(&stream) => {}
+        Ok(Ok(stream)) => match self.handle(stream) {
+          Ok(true) => break Ok(()),
+          Ok(false) => deadline = Instant::now() + self.idle_timeout,
+          Err(Error::Io(_) | Error::MalformedMessage) => {}
+          Err(err) => break Err(err),
+        },
+        Ok(Err(_)) => {}
+        Err(RecvTimeoutError::Timeout) => break Ok(()),
+        Err(RecvTimeoutError::Disconnected) => break Ok(()),
+      }
+    };
+    let _ = fs::remove_file(path);
+    result
+  }
+
+  /// Handles the requests of a single client.  Returns `true` if the agent
+  /// should lock itself.
+  fn handle(&mut self, stream: UnixStream) -> Result<bool, Error> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+      let Ok(request) = serde_json::from_str::<Request>(&line?) else {
+        write_message(
+          &mut writer,
+          &Response::Error {
+            message: "malformed request".to_owned(),
+          },
+        )?;
+        return Err(Error::MalformedMessage);
+      };
+      let lock = matches!(request, Request::Lock);
+      let response = self.respond(request)?;
+      write_message(&mut writer, &response)?;
+      if lock {
+        return Ok(true);
+      }
+    }
+    Ok(false)
+  }
+
+  fn respond(&mut self, request: Request) -> Result<Response, Error> {
+    Ok(match request {
+      Request::Status => Response::Status {
+        fingerprint: self.key_container.state.public.fingerprint.to_string(),
+        idle_timeout: self.idle_timeout.as_secs(),
+      },
+      Request::List => {
+        self
+          .app_state
+          .reload_key_state(&mut self.key_container.state)?;
+        Response::List {
+          credentials: self
+            .key_container
+            .state
+            .secret
+            .ranked(state::unix_time())
+            .into_iter()
+            .map(|c| Entry {
+              domain: c.domain.to_string(),
+              identity: c.identity.to_string(),
+            })
+            .collect(),
+        }
+      }
+      Request::Get {
+        domain,
+        identity,
+        generation,
+      } => {
+        if domain.is_empty() || identity.is_empty() {
+          Response::Error {
+            message: "domain and identity cannot be empty".to_owned(),
+          }
+        } else {
+          let generation = self.app_state.update_key_state(
+            &mut self.key_container.state,
+            |state| {
+              let generation =
+                state.requested_generation(&domain, &identity, generation);
+              if generation.is_some() {
+                state.touch(&domain, &identity);
+              }
+              generation
+            },
+          )?;
+          let Some(generation) = generation else {
+            return Ok(Response::Error {
+              message: format!(
+                "generation cannot be above {}",
+                state::MAX_GENERATION
+              ),
+            });
+          };
+          Response::Password {
+            password: self
+              .key_container
+              .password(&domain, &identity, generation),
+            generation,
+            policy: self.key_container.policy(&domain, &identity),
+          }
+        }
+      }
+      Request::Lock => Response::Locked,
+    })
+  }
+}
+
+fn write_message<T: Serialize>(
+  writer: &mut impl Write,
+  message: &T,
+) -> Result<(), Error> {
+  let Ok(mut line) = serde_json::to_vec(message) else {
+    return Err(Error::MalformedMessage);
+  };
+  line.push(b'\n');
+  writer.write_all(&line)?;
+  Ok(())
+}
+
+/// A connection to a running agent.
+pub struct Client {
+  reader: BufReader<UnixStream>,
+  writer: UnixStream,
+}
+
+impl Client {
+  pub fn connect(path: &Path) -> Result<Self, Error> {
+    let Ok(stream) = UnixStream::connect(path) else {
+      return Err(Error::NotRunning);
+    };
+    Ok(Self {
+      writer: stream.try_clone()?,
+      reader: BufReader::new(stream),
+    })
+  }
+
+  pub fn request(&mut self, request: &Request) -> Result<Response, Error> {
+    write_message(&mut self.writer, request)?;
+    let mut line = String::new();
+    if self.reader.read_line(&mut line)? == 0 {
+      return Err(Error::MalformedMessage);
+    }
+    match serde_json::from_str(&line) {
+      Ok(Response::Error { message }) => Err(Error::Refused(message)),
+      Ok(response) => Ok(response),
+      Err(_) => Err(Error::MalformedMessage),
+    }
+  }
+
+  /// Returns the fingerprint of the unlocked key.
+  pub fn fingerprint(&mut self) -> Result<String, Error> {
+    match self.request(&Request::Status)? {
+      Response::Status { fingerprint, .. } => Ok(fingerprint),
+      _ => Err(Error::MalformedMessage),
+    }
+  }
+
+  pub fn list(&mut self) -> Result<Vec<Entry>, Error> {
+    match self.request(&Request::List)? {
+      Response::List { credentials } => Ok(credentials),
+      _ => Err(Error::MalformedMessage),
+    }
+  }
+
+  /// Returns the password along with its generation and policy.  Without an
+  /// explicit `generation` the one in use for the credentials is picked.
+  pub fn password(
+    &mut self,
+    domain: &str,
+    identity: &str,
+    generation: Option<usize>,
+  ) -> Result<(String, usize, Policy), Error> {
+    match self.request(&Request::Get {
+      domain: domain.to_owned(),
+      identity: identity.to_owned(),
+      generation,
+    })? {
+      Response::Password {
+        password,
+        generation,
+        policy,
+      } => Ok((password, generation, policy)),
+      _ => Err(Error::MalformedMessage),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn test_private_dir() {
+    let dir = std::env::temp_dir()
+      .join(format!("skele-test-agent.{}", std::process::id()));
+    fs::DirBuilder::new().mode(0o755).create(&dir).unwrap();
+    ensure_private_dir(&dir).unwrap();
+    assert_eq!(fs::metadata(&dir).unwrap().mode() & 0o777, 0o700);
+    let listener = bind(&dir.join("agent.sock")).unwrap();
+    let mode = fs::metadata(dir.join("agent.sock")).unwrap().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    let client = UnixStream::connect(dir.join("agent.sock")).unwrap();
+    assert!(is_own_client(&client));
+    drop(listener);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn test_documented_requests_parse() {
+    let parse = |s| serde_json::from_str::<Request>(s).unwrap();
+    assert!(matches!(parse(r#"{"op":"status"}"#), Request::Status));
+    assert!(matches!(parse(r#"{"op":"list"}"#), Request::List));
+    assert!(matches!(parse(r#"{"op":"lock"}"#), Request::Lock));
+    assert!(matches!(
+      parse(r#"{"op":"get","domain":"example.com","identity":"alice"}"#),
+      Request::Get {
+        generation: None,
+        ..
+      }
+    ));
+  }
+
+  #[test]
+  fn test_responses_serialize_as_documented() {
+    let password = Response::Password {
+      password: "Xxxx-Xxxx".to_owned(),
+      generation: 1,
+      policy: Policy::default(),
+    };
+    assert_eq!(
+      serde_json::to_string(&password).unwrap(),
+      r#"{"result":"password","password":"Xxxx-Xxxx","generation":1,"policy":"base32"}"#
+    );
+    assert_eq!(
+      serde_json::to_string(&Response::Locked).unwrap(),
+      r#"{"result":"locked"}"#
+    );
+  }
+}
@@ -0,0 +1,507 @@
+//! # Local API for integrations
+//!
+//! Lets editor plugins and other local tools ask for credentials over JSON-RPC
+//! 2.0.  The API is served only while `skele api` is running and only over a
+//! Unix domain socket accessible by the owner, so it is never reachable from
+//! the network.
+//!
+//! On start the server generates a random session token and writes it to a
+//! file next to the socket (`api.token` beside `api.sock`), readable by the
+//! owner only.  Every call must carry the token.  Calls with a valid token are
+//! still confirmed one by one by the user in the terminal running the server.
+//!
+//! ## Protocol
+//!
+//! The client writes JSON-RPC requests to the socket, one per line, and the
+//! server answers each on a single line.  Notifications (requests without an
+//! `id`) are carried out but not answered.
+//!
+//! - `{"jsonrpc":"2.0","id":1,"method":"list","params":{"token":"…"}}` is
+//!   answered with the known credentials from the most to the least used:
+//!   `{"jsonrpc":"2.0","id":1,"result":[{"domain":"…","identity":"…",
+//!   "count":3,"last_used":1700000000}]}`.
+//! - `{"jsonrpc":"2.0","id":2,"method":"get","params":{"token":"…",
+//!   "domain":"…","identity":"…","generation":0}}` is answered with
+//!   `{"jsonrpc":"2.0","id":2,"result":{"password":"…","generation":0}}`. The
+//!   `generation` is optional and defaults to the generation in use for the
+//!   credentials like with the agent (see [`crate::agent`]), and the
+//!   `generation` of the answer tells which one it was.  It cannot be above
+//!   [`state::MAX_GENERATION`].  Getting a password does not count as a use.
+//! - `{"jsonrpc":"2.0","id":3,"method":"touch","params":{"token":"…",
+//!   "domain":"…","identity":"…"}}` records a use of the credentials and is
+//!   answered with `{"jsonrpc":"2.0","id":3,"result":null}`.
+//!
+//! The key state is read anew for each call and the uses are saved under the
+//! lock of the state file, so the changes other skele invocations make while
+//! the server runs are seen and kept.
+//!
+//! Besides the standard JSON-RPC errors a call may fail with the code `-32001`
+//! when the token is missing or wrong and with `-32002` when the user denied
+//! the call.
+//!
+//! A minimal client lives in `examples/api_client.rs`.
+
+use std::{
+  fs,
+  io::{self, BufRead, BufReader, Write},
+  os::unix::{fs::OpenOptionsExt, net::UnixStream},
+  path::{Path, PathBuf},
+  time::Duration,
+};
+
+use rand_core::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use crate::{agent, state, KeyContainer};
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("i/o error")]
+  Io(#[from] io::Error),
+  #[error("api is already being served")]
+  AlreadyRunning,
+  #[error("confirmation prompt failed")]
+  Prompt(#[source] io::Error),
+  #[error("stopped by the user")]
+  Stopped,
+}
+
+impl From<agent::Error> for Error {
+  fn from(err: agent::Error) -> Self {
+    match err {
+      agent::Error::Io(err) => Error::Io(err),
+      agent::Error::AlreadyRunning => Error::AlreadyRunning,
+      err => Error::Io(io::Error::other(err)),
+    }
+  }
+}
+
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+const INTERNAL_ERROR: i32 = -32603;
+const UNAUTHORIZED: i32 = -32001;
+const DENIED: i32 = -32002;
+
+/// How long the server waits for a connected client to send a request.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Deserialize)]
+struct Call {
+  jsonrpc: String,
+  #[serde(default)]
+  id: Option<Value>,
+  method: String,
+  #[serde(default)]
+  params: Option<Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Params {
+  #[serde(default)]
+  token: String,
+  domain: Option<String>,
+  identity: Option<String>,
+  generation: Option<usize>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Method {
+  List,
+  Get {
+    domain: String,
+    identity: String,
+    generation: Option<usize>,
+  },
+  Touch {
+    domain: String,
+    identity: String,
+  },
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct RpcError {
+  code: i32,
+  message: String,
+}
+
+impl RpcError {
+  fn new(code: i32, message: &str) -> Self {
+    Self {
+      code,
+      message: message.to_owned(),
+    }
+  }
+}
+
+#[derive(Debug, Serialize)]
+struct Reply {
+  jsonrpc: &'static str,
+  id: Value,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  result: Option<Value>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  error: Option<RpcError>,
+}
+
+impl Reply {
+  fn new(id: Value, outcome: Result<Value, RpcError>) -> Self {
+    let (result, error) = match outcome {
+      Ok(result) => (Some(result), None),
+      Err(error) => (None, Some(error)),
+    };
+    Self {
+      jsonrpc: "2.0",
+      id,
+      result,
+      error,
+    }
+  }
+}
+
+/// Returns the default path of the API socket.
+pub fn default_socket_path() -> Result<PathBuf, state::Error> {
+  Ok(state::runtime_dir()?.join("api.sock"))
+}
+
+/// Returns the path of the token file belonging to the socket.
+pub fn token_path(socket_path: &Path) -> PathBuf {
+  socket_path.with_extension("token")
+}
+
+fn generate_token() -> String {
+  let mut bytes = [0u8; 32];
+  rand_core::OsRng.fill_bytes(&mut bytes);
+  bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Writes the token to a file readable by the owner only.
+fn write_token(path: &Path, token: &str) -> io::Result<()> {
+  let _ = fs::remove_file(path);
+  let mut file = fs::OpenOptions::new()
+    .write(true)
+    .create_new(true)
+    .mode(0o600)
+    .open(path)?;
+  file.write_all(token.as_bytes())
+}
+
+/// Compares the tokens in time independent of where they differ.
+fn token_matches(given: &str, expected: &str) -> bool {
+  given.len() == expected.len()
+    && given
+      .bytes()
+      .zip(expected.bytes())
+      .fold(0, |acc, (l, r)| acc | (l ^ r))
+      == 0
+}
+
+/// Parses the call into the token and the method.
+fn parse_call(call: Call) -> Result<(String, Method), RpcError> {
+  if call.jsonrpc != "2.0" {
+    return Err(RpcError::new(INVALID_REQUEST, "invalid request"));
+  }
+  let params = match call.params {
+    Some(params) => serde_json::from_value::<Params>(params)
+      .map_err(|_| RpcError::new(INVALID_PARAMS, "invalid params"))?,
+    None => Params::default(),
+  };
+  let credentials = || match (&params.domain, &params.identity) {
+    (Some(domain), Some(identity))
+      if !domain.is_empty() && !identity.is_empty() =>
+    {
+      Ok((domain.clone(), identity.clone()))
+    }
+    _ => Err(RpcError::new(
+      INVALID_PARAMS,
+      "domain and identity are required",
+    )),
+  };
+  let method = match call.method.as_str() {
+    "list" => Method::List,
+    "get" => {
+      let (domain, identity) = credentials()?;
+      if params.generation.is_some_and(|g| g > state::MAX_GENERATION) {
+        return Err(RpcError::new(INVALID_PARAMS, "generation is too high"));
+      }
+      Method::Get {
+        domain,
+        identity,
+        generation: params.generation,
+      }
+    }
+    "touch" => {
+      let (domain, identity) = credentials()?;
+      Method::Touch { domain, identity }
+    }
+    _ => return Err(RpcError::new(METHOD_NOT_FOUND, "method not found")),
+  };
+  Ok((params.token, method))
+}
+
+/// Describes the method for the confirmation prompt.
+fn describe(method: &Method) -> String {
+  match method {
+    Method::List => "to list the credentials".to_owned(),
+    Method::Get {
+      domain,
+      identity,
+      generation: None,
+    } => format!("to get the password of {} @ {}", identity, domain),
+    Method::Get {
+      domain,
+      identity,
+      generation: Some(generation),
+    } => format!(
+      "to get the password #{} of {} @ {}",
+      generation, identity, domain
+    ),
+    Method::Touch { domain, identity } => {
+      format!("to record a use of {} @ {}", identity, domain)
+    }
+  }
+}
+
+/// Serves the API.  Each call is confirmed with `confirm` which is given a
+/// description of the call and returns whether to allow it or `None` if the
+/// server should stop.
+pub struct Server<'a, F> {
+  app_state: &'a state::AppState,
+  key_container: KeyContainer,
+  token: String,
+  confirm: F,
+}
+
+impl<'a, F> Server<'a, F>
+where
+  F: FnMut(&str) -> io::Result<Option<bool>>,
+{
+  pub fn new(
+    app_state: &'a state::AppState,
+    key_container: KeyContainer,
+    confirm: F,
+  ) -> Self {
+    Self {
+      app_state,
+      key_container,
+      token: generate_token(),
+      confirm,
+    }
+  }
+
+  /// Serves the clients one at a time until stopped.
+  pub fn serve(mut self, path: &Path) -> Result<(), Error> {
+    let listener = agent::bind(path)?;
+    let token_path = token_path(path);
+    write_token(&token_path, &self.token)?;
+    let result = loop {
+      let stream = match listener.accept() {
+        Ok((stream, _)) => stream,
+        // A client that went away before being accepted.
+        Err(err)
+          if matches!(
+            err.kind(),
+            io::ErrorKind::ConnectionAborted | io::ErrorKind::Interrupted
+          ) =>
+        {
+          continue
+        }
+        // Anything else, such as running out of file descriptors, would only
+        // fail again right away.
+        Err(err) => break Err(err.into()),
+      };
+      match self.handle(stream) {
+        Ok(()) | Err(Error::Io(_)) => {}
+        Err(Error::Stopped) => break Ok(()),
+        Err(err) => break Err(err),
+      }
+    };
+    let _ = fs::remove_file(&token_path);
+    let _ = fs::remove_file(path);
+    result
+  }
+
+  fn handle(&mut self, stream: UnixStream) -> Result<(), Error> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+      let reply = match serde_json::from_str::<Call>(&line?) {
+        Ok(call) => {
+          let id = call.id.clone();
+          let outcome = self.respond(call)?;
+          match id {
+            Some(id) => Reply::new(id, outcome),
+            None => continue,
+          }
+        }
+        Err(_) => Reply::new(
+          Value::Null,
+          Err(RpcError::new(PARSE_ERROR, "parse error")),
+        ),
+      };
+      let Ok(mut line) = serde_json::to_vec(&reply) else {
+        continue;
+      };
+      line.push(b'\n');
+      writer.write_all(&line)?;
+    }
+    Ok(())
+  }
+
+  /// Carries out the call.  Only the failures that should stop the server are
+  /// returned as errors; the rest are reported to the client.
+  fn respond(&mut self, call: Call) -> Result<Result<Value, RpcError>, Error> {
+    let (token, method) = match parse_call(call) {
+      Ok(parsed) => parsed,
+      Err(err) => return Ok(Err(err)),
+    };
+    // Check the token before bothering the user.
+    if !token_matches(&token, &self.token) {
+      return Ok(Err(RpcError::new(UNAUTHORIZED, "invalid token")));
+    }
+    match (self.confirm)(&describe(&method)).map_err(Error::Prompt)? {
+      Some(true) => {}
+      Some(false) => return Ok(Err(RpcError::new(DENIED, "request denied"))),
+      None => return Err(Error::Stopped),
+    }
+    if self
+      .app_state
+      .reload_key_state(&mut self.key_container.state)
+      .is_err()
+    {
+      return Ok(Err(RpcError::new(
+        INTERNAL_ERROR,
+        "failed to load key state",
+      )));
+    }
+    Ok(Ok(match method {
+      Method::List => Value::Array(
+        self
+          .key_container
+          .state
+          .secret
+          .ranked(state::unix_time())
+          .into_iter()
+          .map(|c| {
+            json!({
+              "domain": c.domain,
+              "identity": c.identity,
+              "count": c.count,
+              "last_used": c.last_used,
+            })
+          })
+          .collect(),
+      ),
+      Method::Get {
+        domain,
+        identity,
+        generation,
+      } => {
+        let Some(generation) = self
+          .key_container
+          .state
+          .requested_generation(&domain, &identity, generation)
+        else {
+          return Ok(Err(RpcError::new(
+            INVALID_PARAMS,
+            "generation is too high",
+          )));
+        };
+        json!({
+          "password":
+            self.key_container.password(&domain, &identity, generation),
+          "generation": generation,
+        })
+      }
+      Method::Touch { domain, identity } => {
+        if self
+          .app_state
+          .update_key_state(&mut self.key_container.state, |state| {
+            state.touch(&domain, &identity)
+          })
+          .is_err()
+        {
+          return Ok(Err(RpcError::new(
+            INTERNAL_ERROR,
+            "failed to save key state",
+          )));
+        }
+        Value::Null
+      }
+    }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn parse(s: &str) -> Result<(String, Method), RpcError> {
+    parse_call(serde_json::from_str(s).unwrap())
+  }
+
+  #[test]
+  fn test_documented_calls_parse() {
+    assert_eq!(
+      parse(
+        r#"{"jsonrpc":"2.0","id":1,"method":"list","params":{"token":"t"}}"#
+      ),
+      Ok(("t".to_owned(), Method::List))
+    );
+    assert_eq!(
+      parse(
+        r#"{"jsonrpc":"2.0","id":2,"method":"get","params":{"token":"t","domain":"example.com","identity":"alice"}}"#
+      ),
+      Ok((
+        "t".to_owned(),
+        Method::Get {
+          domain: "example.com".to_owned(),
+          identity: "alice".to_owned(),
+          generation: None,
+        }
+      ))
+    );
+  }
+
+  #[test]
+  fn test_invalid_calls_are_rejected() {
+    let code = |s| parse(s).unwrap_err().code;
+    assert_eq!(
+      code(
+        r#"{"jsonrpc":"2.0","id":2,"method":"get","params":{"token":"t","domain":"example.com","identity":"alice","generation":1001}}"#
+      ),
+      INVALID_PARAMS
+    );
+    assert_eq!(
+      code(r#"{"jsonrpc":"1.0","method":"list"}"#),
+      INVALID_REQUEST
+    );
+    assert_eq!(
+      code(r#"{"jsonrpc":"2.0","method":"lock"}"#),
+      METHOD_NOT_FOUND
+    );
+    assert_eq!(
+      code(r#"{"jsonrpc":"2.0","method":"touch","params":{"domain":"x"}}"#),
+      INVALID_PARAMS
+    );
+    assert_eq!(
+      code(r#"{"jsonrpc":"2.0","method":"list","params":{"tokn":"t"}}"#),
+      INVALID_PARAMS
+    );
+  }
+
+  #[test]
+  fn test_token_matches() {
+    let token = generate_token();
+    assert_eq!(token.len(), 64);
+    assert!(token_matches(&token, &token));
+    assert!(!token_matches("", &token));
+    assert!(!token_matches(&token[1..], &token));
+    assert!(!token_matches(&generate_token(), &token));
+  }
+}
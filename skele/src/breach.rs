@@ -0,0 +1,201 @@
+//! # Offline breach check
+//!
+//! Looks the passwords up in a local copy of the Pwned Passwords list of Have
+//! I Been Pwned so that no password, not even a part of its hash, leaves the
+//! machine.  The list comes in two shapes, both of which are supported:
+//!
+//! - a single file of `HASH:COUNT` lines ordered by the hash, as in
+//!   `pwned-passwords-sha1-ordered-by-hash-v8.txt`, and
+//! - a directory of range files named after the first five hex digits of the
+//!   hash, e.g. `21BD1.txt`, holding `SUFFIX:COUNT` lines ordered by the rest
+//!   of the hash, as written by the official downloader.
+//!
+//! Either way the lines are found by binary search over the file so that even
+//! the tens of gigabytes of the full list are searched in a few dozen reads.
+
+use std::{
+  cmp::Ordering,
+  fmt::Write as _,
+  fs,
+  io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+  path::{Path, PathBuf},
+};
+
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("i/o error")]
+  Io(#[from] io::Error),
+  #[error("the range file {0} is missing from the list")]
+  MissingRange(PathBuf),
+  #[error("malformed line in the list: {0}")]
+  Malformed(String),
+}
+
+/// The number of hex digits in the names of the range files.
+const PREFIX_LEN: usize = 5;
+
+/// A local copy of the Pwned Passwords list.
+pub struct PwnedList {
+  path: PathBuf,
+  ranges: bool,
+}
+
+impl PwnedList {
+  pub fn open(path: &Path) -> Result<Self, Error> {
+    let metadata = fs::metadata(path)?;
+    Ok(Self {
+      path: path.to_owned(),
+      ranges: metadata.is_dir(),
+    })
+  }
+
+  /// Returns how many times the password has been seen in breaches or zero if
+  /// it is not in the list.
+  pub fn count(&self, password: &str) -> Result<u64, Error> {
+    let hash = sha1_hex(password);
+    if self.ranges {
+      let (prefix, suffix) = hash.split_at(PREFIX_LEN);
+      let path = self.path.join(format!("{}.txt", prefix));
+      let file = match fs::File::open(&path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+          return Err(Error::MissingRange(path))
+        }
+        Err(err) => return Err(err.into()),
+      };
+      search(file, suffix)
+    } else {
+      search(fs::File::open(&self.path)?, &hash)
+    }
+  }
+}
+
+/// Returns the SHA-1 hash of the password in upper case hex like in the list.
+fn sha1_hex(password: &str) -> String {
+  Sha1::digest(password.as_bytes()).iter().fold(
+    String::with_capacity(40),
+    |mut hex, octet| {
+      let _ = write!(hex, "{:02X}", octet);
+      hex
+    },
+  )
+}
+
+/// Finds the line of the key from the `KEY:COUNT` lines ordered by the key
+/// and returns the count or zero if there is no such line.
+fn search<R: Read + Seek>(file: R, key: &str) -> Result<u64, Error> {
+  let mut reader = BufReader::new(file);
+  let len = reader.seek(SeekFrom::End(0))?;
+  // The lines starting before `lo` have a key less than the one searched
+  // for and those starting at or after `hi` have a greater or equal key.
+  let (mut lo, mut hi) = (0, len);
+  while lo < hi {
+    let mid = lo + (hi - lo) / 2;
+    match line_at(&mut reader, mid)? {
+      Some((start, line)) if start < hi => match compare(&line, key)? {
+        Ordering::Less => lo = start + line.len() as u64,
+        _ => hi = mid,
+      },
+      _ => hi = mid,
+    }
+  }
+  match line_at(&mut reader, lo)? {
+    Some((_, line)) if compare(&line, key)? == Ordering::Equal => {
+      let count = line.trim_end().split_once(':').map_or("1", |(_, c)| c);
+      count.parse().map_err(|_| Error::Malformed(line))
+    }
+    _ => Ok(0),
+  }
+}
+
+/// Returns the first line starting at or after the offset along with its
+/// offset.  The line keeps its line break.
+fn line_at<R: BufRead + Seek>(
+  reader: &mut R,
+  offset: u64,
+) -> Result<Option<(u64, String)>, Error> {
+  let mut start = offset;
+  if offset > 0 {
+    // The line starts right after the previous line break.
+    reader.seek(SeekFrom::Start(offset - 1))?;
+    let mut skipped = Vec::new();
+    start += reader.read_until(b'\n', &mut skipped)? as u64 - 1;
+  } else {
+    reader.seek(SeekFrom::Start(0))?;
+  }
+  let mut line = String::new();
+  if reader.read_line(&mut line)? == 0 {
+    return Ok(None);
+  }
+  Ok(Some((start, line)))
+}
+
+/// Compares the key of the line with the key searched for.
+fn compare(line: &str, key: &str) -> Result<Ordering, Error> {
+  let line_key = line.trim_end().split(':').next().unwrap_or_default();
+  if line_key.len() != key.len() {
+    return Err(Error::Malformed(line.trim_end().to_owned()));
+  }
+  Ok(line_key.to_ascii_uppercase().as_str().cmp(key))
+}
+
+#[cfg(test)]
+mod tests {
+
+  use std::io::Cursor;
+
+  use super::*;
+
+  #[test]
+  fn test_sha1_hex() {
+    assert_eq!(
+      sha1_hex("password"),
+      "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8"
+    );
+  }
+
+  #[test]
+  fn test_search() {
+    let keys = ["0A", "1B", "2C", "3D", "4E", "5F", "6a", "7B"];
+    let list = keys
+      .iter()
+      .enumerate()
+      .map(|(i, key)| format!("{}:{}\r\n", key, i * 100 + 1))
+      .collect::<String>();
+    let search = |key: &str| search(Cursor::new(list.as_bytes()), key).unwrap();
+    for (i, key) in keys.iter().enumerate() {
+      assert_eq!(search(&key.to_ascii_uppercase()), i as u64 * 100 + 1);
+    }
+    for key in ["00", "0B", "55", "6B", "FF"] {
+      assert_eq!(search(key), 0);
+    }
+    assert_eq!(super::search(Cursor::new(b""), "0A").unwrap(), 0);
+    assert!(matches!(
+      super::search(Cursor::new(b"ABC:1\n"), "0A"),
+      Err(Error::Malformed(_))
+    ));
+  }
+
+  #[test]
+  fn test_ranges() {
+    let dir = std::env::temp_dir()
+      .join(format!("skele-test-breach.{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+      dir.join("5BAA6.txt"),
+      concat!(
+        "003D68EB55068C33ACE09247EE4C639306B:3\r\n",
+        "1E4C9B93F3F0682250B6CF8331B7EE68FD8:9659365\r\n",
+        "1E4C9B93F3F0682250B6CF8331B7EE68FD9:2\r\n",
+      ),
+    )
+    .unwrap();
+    let list = PwnedList::open(&dir).unwrap();
+    assert_eq!(list.count("password").unwrap(), 9659365);
+    assert!(matches!(list.count("hunter2"), Err(Error::MissingRange(_))));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -1,7 +1,7 @@
 //! # Ciphered serializable container
 
 use aes_gcm::{
-  aead::{Aead, AeadCore},
+  aead::{Aead, AeadCore, Payload},
   Aes256Gcm, KeyInit, Nonce,
 };
 use rand_core::RngCore;
@@ -66,6 +66,40 @@ impl Ciphered {
     })
   }
 
+  /// Encrypts the octets as they are.  The associated data is authenticated
+  /// but not included; the same has to be given to [`Ciphered::open`].
+  pub fn seal(octets: &[u8], key: &[u8], aad: &[u8]) -> Result<Self, Error> {
+    let mut nonce = [0u8; 12];
+    rand_core::OsRng.fill_bytes(&mut nonce);
+    let aes_gcm = aes_gcm(key);
+    let aes_gcm_nonce = Aes256GcmNonce::from_slice(&nonce);
+    let Ok(ciphered) =
+      aes_gcm.encrypt(aes_gcm_nonce, Payload { msg: octets, aad })
+    else {
+      return Err(Error::CipherOpFailed);
+    };
+    Ok(Self {
+      alg: CipherAlgorithm::AesGcm256,
+      nonce,
+      ciphered: ciphered.into(),
+    })
+  }
+
+  /// Decrypts the octets sealed with [`Ciphered::seal`].
+  pub fn open(&self, key: &[u8], aad: &[u8]) -> Result<Vec<u8>, Error> {
+    let aes_gcm = aes_gcm(key);
+    let aes_gcm_nonce = Aes256GcmNonce::from_slice(&self.nonce);
+    aes_gcm
+      .decrypt(
+        aes_gcm_nonce,
+        Payload {
+          msg: &self.ciphered,
+          aad,
+        },
+      )
+      .map_err(|_| Error::CipherOpFailed)
+  }
+
   pub fn decipher<T>(&self, key: &[u8]) -> Result<T, Error>
   where
     T: DeserializeOwned,
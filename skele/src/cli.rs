@@ -0,0 +1,357 @@
+//! # Command line interface
+
+use std::{path::PathBuf, time::Duration};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use rustybones::Policy;
+use thiserror::Error;
+
+use crate::{clipboard, import, inventory};
+
+/// Arguments that parse but cannot be used.
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error(
+    "the policy has {0:.1} bits of entropy, less than the minimum of {1}; set \
+     it with --allow-weak"
+  )]
+  WeakPolicy(f64, u32),
+}
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+  #[command(subcommand)]
+  pub command: Option<Command>,
+  /// Output format
+  #[arg(
+    long,
+    global = true,
+    env = "SKELE_FORMAT",
+    value_enum,
+    default_value_t
+  )]
+  pub format: Format,
+  /// Path of the agent socket
+  #[arg(long, global = true, env = "SKELE_AGENT_SOCKET")]
+  pub agent_socket: Option<PathBuf>,
+  /// Seconds after which a copied password is cleared from the clipboard
+  #[arg(
+    long,
+    global = true,
+    env = "SKELE_CLIPBOARD_TIMEOUT",
+    default_value_t = 45,
+    value_parser = clap::value_parser!(u64).range(1..)
+  )]
+  pub clipboard_timeout: u64,
+  /// Clipboard backend to use
+  #[arg(
+    long,
+    global = true,
+    env = "SKELE_CLIPBOARD",
+    value_enum,
+    default_value_t
+  )]
+  pub clipboard: clipboard::Kind,
+  /// Program the command clipboard backend pipes the password to (e.g.
+  /// "xclip -selection clipboard")
+  #[arg(long, global = true, env = "SKELE_CLIPBOARD_COMMAND")]
+  pub clipboard_command: Option<String>,
+  /// Put the password into the primary selection instead of the clipboard
+  #[arg(long, global = true, env = "SKELE_PRIMARY")]
+  pub primary: bool,
+  /// Program the "Emit to command" action pipes the password to (e.g.
+  /// "xdotool type --file -")
+  #[arg(long, global = true, env = "SKELE_EMIT_COMMAND")]
+  pub emit_command: Option<String>,
+  /// Seconds to wait before emitting so that the target window can be
+  /// focused
+  #[arg(long, global = true, env = "SKELE_EMIT_DELAY", default_value_t = 3)]
+  pub emit_delay: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+  /// Styled text for humans
+  #[default]
+  Text,
+  /// One JSON record per line on the standard output
+  Json,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+  /// Unlock a key and serve passwords to other invocations until locked
+  Agent {
+    /// Seconds of inactivity after which the agent locks itself
+    #[arg(long, env = "SKELE_AGENT_IDLE_TIMEOUT", default_value_t = 900)]
+    idle_timeout: u64,
+  },
+  /// Lock the running agent
+  Lock,
+  /// Browse the credentials in a full-screen terminal interface
+  Tui {
+    /// Seconds of inactivity after which the interface locks itself
+    #[arg(long, env = "SKELE_LOCK_TIMEOUT", default_value_t = 300)]
+    lock_timeout: u64,
+  },
+  /// Get the password for the given domain and identity
+  Get {
+    domain: String,
+    identity: String,
+    /// Which generation of the password to get instead of the one in use
+    #[arg(long)]
+    generation: Option<usize>,
+    /// Show the password instead of copying it to the clipboard
+    #[arg(long)]
+    reveal: bool,
+  },
+  /// Show or set the password policy of the given domain and identity
+  ///
+  /// The policies are "base32:<length>" (e.g. "base32:32"),
+  /// "words:<list>:<count>:<separator>" (e.g. "words:bip39:7:-"), and
+  /// "pin:<digits>[:skip-weak]" (e.g. "pin:6:skip-weak").  The parameters can
+  /// be left out from the end. Changing the policy changes the password.
+  ///
+  /// A policy whose passwords have less entropy than the minimum is refused
+  /// unless explicitly allowed.  This includes the PIN policies, as even a
+  /// twelve-digit PIN is below the default minimum; set the length the bank
+  /// or the device asks for with --allow-weak.
+  Policy {
+    domain: String,
+    identity: String,
+    policy: Option<Policy>,
+    /// Bits of entropy below which a policy is refused
+    #[arg(long, env = "SKELE_MIN_ENTROPY", default_value_t = 40)]
+    min_entropy: u32,
+    /// Set the policy even if it is below the minimum entropy
+    #[arg(long)]
+    allow_weak: bool,
+  },
+  /// Show or set the tags of the given domain and identity
+  ///
+  /// The tags given replace the current ones.
+  Tags {
+    domain: String,
+    identity: String,
+    tags: Vec<String>,
+    /// Remove all tags
+    #[arg(long, conflicts_with = "tags")]
+    clear: bool,
+  },
+  /// Write an inventory of the known credentials for audits
+  ///
+  /// The inventory lists the domain, identity, tags, generation, time of the
+  /// last use, policy, and whether a stored password is in use.  The
+  /// passwords are included only when asked for and confirmed.
+  Export {
+    /// Format of the inventory
+    #[arg(value_name = "FORMAT")]
+    layout: inventory::Format,
+    /// Path of the inventory file
+    #[arg(long, short)]
+    output: PathBuf,
+    /// Include the passwords in plain text
+    #[arg(long)]
+    with_passwords: bool,
+    /// Replace an existing file
+    #[arg(long)]
+    force: bool,
+  },
+  /// Manage the passwords stored for accounts whose password cannot be
+  /// changed to the derived one
+  Stored {
+    #[command(subcommand)]
+    command: StoredCommand,
+  },
+  /// Manage the security questions and show their derived answers
+  Question {
+    #[command(subcommand)]
+    command: QuestionCommand,
+  },
+  /// Manage secret notes such as recovery codes, edited with $EDITOR
+  Note {
+    #[command(subcommand)]
+    command: NoteCommand,
+  },
+  /// Manage the one-time password secrets issued by services
+  Totp {
+    #[command(subcommand)]
+    command: TotpCommand,
+  },
+  /// Write the OpenSSH key pair derived for the given domain and identity
+  SshKey {
+    domain: String,
+    identity: String,
+    /// What the key is for; each purpose has a key pair of its own
+    #[arg(long, default_value = "ssh")]
+    purpose: String,
+    /// Path of the private key file; the public key goes next to it with the
+    /// ".pub" extension
+    #[arg(long, short)]
+    output: PathBuf,
+    /// Comment of the key instead of "identity@domain"
+    #[arg(long)]
+    comment: Option<String>,
+    /// Protect the private key file with a passphrase (asked for)
+    #[arg(long)]
+    passphrase: bool,
+    /// Replace existing key files
+    #[arg(long)]
+    force: bool,
+  },
+  /// Import the accounts exported from another password manager and tell
+  /// which ones have a password other than the derived one
+  Import {
+    /// Password manager the export comes from
+    #[arg(value_name = "FORMAT")]
+    source: import::Format,
+    /// Path of the export file or, for pass, of the store directory
+    path: PathBuf,
+    /// Store the passwords that differ from the derived ones to keep using
+    /// them (see "skele stored")
+    #[arg(long)]
+    store_differing: bool,
+    /// Show what would be imported without changing anything
+    #[arg(long)]
+    dry_run: bool,
+  },
+  /// Check the passwords of the known credentials against a local copy of
+  /// the Pwned Passwords list of Have I Been Pwned
+  ///
+  /// The list is either the single file ordered by hash or the directory of
+  /// range files written by the official downloader.  Nothing is sent over
+  /// the network.
+  Breaches {
+    /// Path of the list file or directory
+    list: PathBuf,
+  },
+  /// Encrypt a file with a key derived from the skeleton key
+  Encrypt {
+    input: PathBuf,
+    /// Path of the encrypted file instead of the input path with the
+    /// ".skele" extension added
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+    /// Replace an existing file
+    #[arg(long)]
+    force: bool,
+  },
+  /// Decrypt a file encrypted with the same skeleton key
+  Decrypt {
+    input: PathBuf,
+    /// Path of the decrypted file instead of the input path with the
+    /// ".skele" extension taken away
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+    /// Replace an existing file
+    #[arg(long)]
+    force: bool,
+  },
+  /// Unlock a key and serve local integrations over a JSON-RPC API, asking
+  /// to confirm each call
+  Api {
+    /// Path of the API socket
+    #[arg(long, env = "SKELE_API_SOCKET")]
+    socket: Option<PathBuf>,
+  },
+  /// Serve a browser extension over the native messaging protocol
+  NativeHost {
+    /// Also serve the credentials of a domain to the pages of its subdomains
+    #[arg(long)]
+    subdomains: bool,
+    /// Arguments passed by the browser (ignored)
+    #[arg(hide = true, allow_hyphen_values = true)]
+    browser_args: Vec<String>,
+  },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TotpCommand {
+  /// Import the otpauth URI of a secret issued for the given domain and
+  /// identity (asked for so that it stays out of the shell history)
+  Add { domain: String, identity: String },
+  /// Remove an imported secret of the given domain and identity
+  Remove {
+    domain: String,
+    identity: String,
+    /// Which of the imported secrets to remove, counting from zero (asked for
+    /// if there are several)
+    #[arg(long)]
+    index: Option<usize>,
+  },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum StoredCommand {
+  /// Store the password of the given domain and identity (asked for) to be
+  /// used instead of the derived one
+  Set { domain: String, identity: String },
+  /// Remove the stored password of the given domain and identity so that the
+  /// derived one is used again
+  Clear { domain: String, identity: String },
+  /// List the credentials that are still on stored passwords
+  Report,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum QuestionCommand {
+  /// Store a security question of the given domain and identity and show
+  /// its answer
+  Add {
+    domain: String,
+    identity: String,
+    question: String,
+  },
+  /// Remove a security question of the given domain and identity
+  Remove {
+    domain: String,
+    identity: String,
+    question: String,
+  },
+  /// Show the security questions of the given domain and identity with
+  /// their answers
+  List { domain: String, identity: String },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum NoteCommand {
+  /// Write a new note in the editor
+  Add {
+    title: String,
+    /// Domain of the credentials the note belongs to
+    #[arg(long, requires = "identity")]
+    domain: Option<String>,
+    /// Identity of the credentials the note belongs to
+    #[arg(long, requires = "domain")]
+    identity: Option<String>,
+  },
+  /// Show a note
+  View { title: String },
+  /// Change a note in the editor
+  Edit { title: String },
+  /// Delete a note
+  Delete { title: String },
+  /// List the notes, or those of the given domain and identity
+  List {
+    #[arg(long, requires = "identity")]
+    domain: Option<String>,
+    #[arg(long, requires = "domain")]
+    identity: Option<String>,
+  },
+}
+
+impl Cli {
+  pub fn clipboard_options(&self) -> clipboard::Options {
+    clipboard::Options {
+      kind: self.clipboard,
+      selection: if self.primary {
+        clipboard::Selection::Primary
+      } else {
+        clipboard::Selection::Clipboard
+      },
+      command: self.clipboard_command.clone(),
+      timeout: Duration::from_secs(self.clipboard_timeout),
+    }
+  }
+}
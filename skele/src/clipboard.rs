@@ -1,26 +1,151 @@
+//! # Clipboard access
+//!
+//! The copied key is kept in the clipboard for a limited time only.  While
+//! waiting we show a countdown and serve any number of paste requests.  Once
+//! the time is up the clipboard is cleared unless some other application has
+//! taken it over in the meantime.
+//!
+//! The clipboard is accessed through one of the pluggable backends.  By default
+//! the first backend that is available in the running environment is used.
+
+use std::{io, time::Duration};
+
+use clap::ValueEnum;
+use thiserror::Error;
+
+use crate::out;
+
+mod command;
+
+pub use command::split_command_line;
+mod osc52;
+mod system;
 #[cfg(target_os = "linux")]
-mod inner {
-  use wl_clipboard_rs::copy::{MimeType, Options, ServeRequests, Source};
-
-  pub fn copy(key: &str) {
-    let mut options = Options::new();
-    options
-      .foreground(true)
-      .trim_newline(true)
-      .serve_requests(ServeRequests::Only(1));
-    options
-      .copy(Source::Bytes(key.as_bytes().into()), MimeType::Text)
-      .unwrap();
-  }
+mod wayland;
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("i/o error")]
+  Io(#[from] io::Error),
+  #[error("clipboard operation failed")]
+  ClipboardOpFailed,
+  #[error("no clipboard available")]
+  Unavailable,
+}
+
+/// A way of getting the key into the clipboard.
+pub trait Backend {
+  /// Tells whether the backend looks usable in the running environment.
+  fn is_available(&self) -> bool;
+
+  /// Puts the key into the clipboard.
+  fn acquire(&self, key: &str) -> Result<Box<dyn Lease>, Error>;
 }
 
-#[cfg(target_os = "macos")]
-mod inner {
+/// The key put into the clipboard by a backend.
+pub trait Lease {
+  /// Tells whether the clipboard still holds the key.  Backends that cannot
+  /// tell assume that it does.
+  fn is_held(&mut self) -> bool;
+
+  /// Clears the clipboard if it still holds the key.
+  fn release(self: Box<Self>) -> Result<(), Error>;
+}
 
-  pub fn copy(key: &str) {
-    let mut clipboard = arboard::Clipboard::new().unwrap();
-    clipboard.set_text(key).unwrap();
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Kind {
+  /// Use the first available backend
+  #[default]
+  Auto,
+  /// Wayland data control protocol (Linux only)
+  Wayland,
+  /// X11 selections on Linux, the pasteboard on macOS
+  #[value(alias = "x11", alias = "macos")]
+  System,
+  /// OSC 52 terminal escape sequence
+  Osc52,
+  /// External program such as `wl-copy` or `xclip`
+  Command,
+  /// Never use the clipboard
+  None,
+}
+
+/// The selection the key is put into.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Selection {
+  /// The selection used for explicit copy and paste
+  #[default]
+  Clipboard,
+  /// The selection pasted with the middle mouse button (not on macOS)
+  Primary,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+  pub kind: Kind,
+  pub selection: Selection,
+  /// The command line of the program used by the command backend.  When not
+  /// given a known program is looked up from `PATH`.
+  pub command: Option<String>,
+  pub timeout: Duration,
+}
+
+fn backends(options: &Options) -> Vec<Box<dyn Backend>> {
+  let command = || -> Box<dyn Backend> {
+    match options.command.as_deref() {
+      Some(command_line) => Box::new(command::Command::custom(command_line)),
+      None => Box::new(command::Command::detect(options.selection)),
+    }
+  };
+  let selection = options.selection;
+  match options.kind {
+    Kind::Auto => vec![
+      #[cfg(target_os = "linux")]
+      Box::new(wayland::Wayland(selection)),
+      Box::new(system::System(selection)),
+      command(),
+      Box::new(osc52::Osc52(selection)),
+    ],
+    #[cfg(target_os = "linux")]
+    Kind::Wayland => vec![Box::new(wayland::Wayland(selection))],
+    #[cfg(not(target_os = "linux"))]
+    Kind::Wayland => vec![],
+    Kind::System => vec![Box::new(system::System(selection))],
+    Kind::Osc52 => vec![Box::new(osc52::Osc52(selection))],
+    Kind::Command => vec![command()],
+    Kind::None => vec![],
   }
 }
 
-pub use inner::*;
+/// Puts the key into the clipboard using the first backend that works.
+pub fn acquire(key: &str, options: &Options) -> Result<Box<dyn Lease>, Error> {
+  for backend in backends(options) {
+    if !backend.is_available() {
+      continue;
+    }
+    match backend.acquire(key) {
+      Ok(lease) => return Ok(lease),
+      // In the automatic mode a failing backend is just not available.
+      Err(_) if options.kind == Kind::Auto => continue,
+      Err(err) => return Err(err),
+    }
+  }
+  Err(Error::Unavailable)
+}
+
+/// Copies the key into the clipboard and clears it after the timeout or as
+/// soon as the user presses a key.
+pub fn copy(key: &str, options: &Options) -> Result<(), Error> {
+  let mut lease = acquire(key, options)?;
+  let held =
+    out::countdown("Copied", "clearing clipboard", options.timeout, || {
+      lease.is_held()
+    })?;
+  lease.release()?;
+  if held {
+    out::info("Clipboard", "cleared")?;
+  } else {
+    out::info("Clipboard", "taken over by another application")?;
+  }
+  Ok(())
+}
@@ -0,0 +1,228 @@
+//! # External program clipboard backend
+//!
+//! Pipes the key to a program such as `wl-copy` or `xclip`.  The key is always
+//! passed through the standard input and never on the command line.
+
+use std::{
+  env,
+  io::Write,
+  path::Path,
+  process::{self, Stdio},
+  time::{Duration, Instant},
+};
+
+use super::{Backend, Error, Lease, Selection};
+
+/// A clipboard tool known to us.  `paste` and `clear` are optional; without
+/// `paste` we cannot tell whether the clipboard still holds the key and
+/// without `clear` the clipboard is cleared by copying an empty string.
+struct Tool {
+  selection: Selection,
+  /// The environment variable that must be set for the tool to work.
+  display_var: Option<&'static str>,
+  copy: &'static [&'static str],
+  paste: Option<&'static [&'static str]>,
+  clear: Option<&'static [&'static str]>,
+}
+
+const KNOWN_TOOLS: &[Tool] = &[
+  Tool {
+    selection: Selection::Clipboard,
+    display_var: Some("WAYLAND_DISPLAY"),
+    copy: &["wl-copy", "--trim-newline"],
+    paste: Some(&["wl-paste", "--no-newline"]),
+    clear: Some(&["wl-copy", "--clear"]),
+  },
+  Tool {
+    selection: Selection::Primary,
+    display_var: Some("WAYLAND_DISPLAY"),
+    copy: &["wl-copy", "--primary", "--trim-newline"],
+    paste: Some(&["wl-paste", "--primary", "--no-newline"]),
+    clear: Some(&["wl-copy", "--primary", "--clear"]),
+  },
+  Tool {
+    selection: Selection::Clipboard,
+    display_var: Some("DISPLAY"),
+    copy: &["xclip", "-selection", "clipboard", "-in"],
+    paste: Some(&["xclip", "-selection", "clipboard", "-out"]),
+    clear: None,
+  },
+  Tool {
+    selection: Selection::Primary,
+    display_var: Some("DISPLAY"),
+    copy: &["xclip", "-selection", "primary", "-in"],
+    paste: Some(&["xclip", "-selection", "primary", "-out"]),
+    clear: None,
+  },
+  Tool {
+    selection: Selection::Clipboard,
+    display_var: Some("DISPLAY"),
+    copy: &["xsel", "--clipboard", "--input"],
+    paste: Some(&["xsel", "--clipboard", "--output"]),
+    clear: Some(&["xsel", "--clipboard", "--delete"]),
+  },
+  Tool {
+    selection: Selection::Primary,
+    display_var: Some("DISPLAY"),
+    copy: &["xsel", "--primary", "--input"],
+    paste: Some(&["xsel", "--primary", "--output"]),
+    clear: Some(&["xsel", "--primary", "--delete"]),
+  },
+  Tool {
+    selection: Selection::Clipboard,
+    display_var: None,
+    copy: &["pbcopy"],
+    paste: Some(&["pbpaste"]),
+    clear: None,
+  },
+];
+
+/// How often we are willing to run the paste program to check whether the
+/// clipboard still holds the key.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Splits a command line into the program and its arguments at whitespace.
+pub fn split_command_line(command_line: &str) -> Vec<String> {
+  command_line.split_whitespace().map(str::to_owned).collect()
+}
+
+fn is_in_path(program: &str) -> bool {
+  if program.contains('/') {
+    return Path::new(program).is_file();
+  }
+  env::var_os("PATH").is_some_and(|paths| {
+    env::split_paths(&paths).any(|dir| dir.join(program).is_file())
+  })
+}
+
+/// Runs the program with `input` written to its standard input and waits for
+/// it to finish.  Returns the standard output when `capture` is set.
+fn run(argv: &[String], input: &[u8], capture: bool) -> Result<Vec<u8>, Error> {
+  let Some((program, args)) = argv.split_first() else {
+    return Err(Error::ClipboardOpFailed);
+  };
+  let mut child = process::Command::new(program)
+    .args(args)
+    .stdin(Stdio::piped())
+    .stdout(if capture {
+      Stdio::piped()
+    } else {
+      Stdio::null()
+    })
+    .stderr(Stdio::null())
+    .spawn()?;
+  if let Some(mut stdin) = child.stdin.take() {
+    stdin.write_all(input)?;
+  }
+  let output = child.wait_with_output()?;
+  if output.status.success() {
+    Ok(output.stdout)
+  } else {
+    Err(Error::ClipboardOpFailed)
+  }
+}
+
+fn to_argv(argv: &[&str]) -> Vec<String> {
+  argv.iter().map(|&arg| arg.to_owned()).collect()
+}
+
+pub struct Command {
+  copy: Vec<String>,
+  paste: Option<Vec<String>>,
+  clear: Option<Vec<String>>,
+}
+
+impl Command {
+  /// Uses the given command line for copying.
+  pub fn custom(command_line: &str) -> Self {
+    Self {
+      copy: split_command_line(command_line),
+      paste: None,
+      clear: None,
+    }
+  }
+
+  /// Uses the first known tool for the selection that is found from `PATH`
+  /// and that looks usable in the running environment.
+  pub fn detect(selection: Selection) -> Self {
+    let tool = KNOWN_TOOLS.iter().find(|tool| {
+      tool.selection == selection
+        && tool
+          .display_var
+          .is_none_or(|var| env::var_os(var).is_some_and(|v| !v.is_empty()))
+        && is_in_path(tool.copy[0])
+    });
+    match tool {
+      Some(tool) => Self {
+        copy: to_argv(tool.copy),
+        paste: tool.paste.map(to_argv),
+        clear: tool.clear.map(to_argv),
+      },
+      None => Self {
+        copy: vec![],
+        paste: None,
+        clear: None,
+      },
+    }
+  }
+}
+
+impl Backend for Command {
+  fn is_available(&self) -> bool {
+    self.copy.first().is_some_and(|program| is_in_path(program))
+  }
+
+  fn acquire(&self, key: &str) -> Result<Box<dyn Lease>, Error> {
+    run(&self.copy, key.as_bytes(), false)?;
+    Ok(Box::new(CommandLease {
+      copy: self.copy.clone(),
+      paste: self.paste.clone(),
+      clear: self.clear.clone(),
+      key: key.to_owned(),
+      held: true,
+      checked_at: Instant::now(),
+    }))
+  }
+}
+
+struct CommandLease {
+  copy: Vec<String>,
+  paste: Option<Vec<String>>,
+  clear: Option<Vec<String>>,
+  key: String,
+  held: bool,
+  checked_at: Instant,
+}
+
+impl CommandLease {
+  fn check(&mut self) {
+    if let Some(paste) = &self.paste {
+      self.held = run(paste, &[], true)
+        .is_ok_and(|contents| contents == self.key.as_bytes());
+    }
+    self.checked_at = Instant::now();
+  }
+}
+
+impl Lease for CommandLease {
+  fn is_held(&mut self) -> bool {
+    if self.held && self.checked_at.elapsed() >= CHECK_INTERVAL {
+      self.check();
+    }
+    self.held
+  }
+
+  fn release(mut self: Box<Self>) -> Result<(), Error> {
+    if self.held {
+      self.check();
+    }
+    if !self.held {
+      return Ok(());
+    }
+    match &self.clear {
+      Some(clear) => run(clear, &[], false)?,
+      None => run(&self.copy, &[], false)?,
+    };
+    Ok(())
+  }
+}
@@ -0,0 +1,68 @@
+//! # OSC 52 terminal clipboard backend
+//!
+//! Asks the terminal emulator to set the clipboard with the OSC 52 escape
+//! sequence.  This works over SSH and inside tmux (given that the terminal and
+//! tmux allow it) but there is no way to tell whether the terminal honoured the
+//! request or whether the clipboard still holds the key.
+
+use std::{
+  env,
+  io::{self, IsTerminal, Write},
+};
+
+use base64ct::Encoding;
+
+use super::{Backend, Error, Lease, Selection};
+
+pub struct Osc52(pub Selection);
+
+/// Writes the OSC 52 sequence setting the selection to `payload`.  The payload
+/// is expected to be Base64 encoded; anything else clears the selection.
+fn write_sequence(selection: Selection, payload: &str) -> io::Result<()> {
+  let target = match selection {
+    Selection::Clipboard => 'c',
+    Selection::Primary => 'p',
+  };
+  let sequence = format!("\x1B]52;{};{}\x07", target, payload);
+  let mut stdout = io::stdout();
+  if env::var_os("TMUX").is_some() {
+    // Pass the sequence through tmux to the outer terminal.
+    write!(
+      stdout,
+      "\x1BPtmux;{}\x1B\\",
+      sequence.replace('\x1B', "\x1B\x1B")
+    )?;
+  } else {
+    write!(stdout, "{}", sequence)?;
+  }
+  stdout.flush()
+}
+
+impl Backend for Osc52 {
+  /// We cannot query the terminal for support, so in the automatic mode we
+  /// count on it only when running remotely or inside tmux.
+  fn is_available(&self) -> bool {
+    io::stdout().is_terminal()
+      && ["SSH_TTY", "SSH_CONNECTION", "TMUX"]
+        .iter()
+        .any(|var| env::var_os(var).is_some())
+  }
+
+  fn acquire(&self, key: &str) -> Result<Box<dyn Lease>, Error> {
+    write_sequence(self.0, &base64ct::Base64::encode_string(key.as_bytes()))?;
+    Ok(Box::new(Osc52Lease(self.0)))
+  }
+}
+
+struct Osc52Lease(Selection);
+
+impl Lease for Osc52Lease {
+  fn is_held(&mut self) -> bool {
+    true
+  }
+
+  fn release(self: Box<Self>) -> Result<(), Error> {
+    write_sequence(self.0, "!")?;
+    Ok(())
+  }
+}
@@ -0,0 +1,129 @@
+//! # System clipboard backend
+//!
+//! Uses X11 selections on Linux and the pasteboard on macOS.  The primary
+//! selection is available on Linux only.
+
+use super::{Backend, Error, Lease, Selection};
+
+pub struct System(pub Selection);
+
+#[cfg(target_os = "linux")]
+mod ops {
+  use arboard::{
+    ClearExtLinux, Clipboard, GetExtLinux, LinuxClipboardKind, SetExtLinux,
+  };
+
+  use super::Selection;
+
+  fn kind(selection: Selection) -> LinuxClipboardKind {
+    match selection {
+      Selection::Clipboard => LinuxClipboardKind::Clipboard,
+      Selection::Primary => LinuxClipboardKind::Primary,
+    }
+  }
+
+  pub fn is_available(_selection: Selection) -> bool {
+    std::env::var_os("DISPLAY").is_some_and(|display| !display.is_empty())
+  }
+
+  pub fn set(
+    clipboard: &mut Clipboard,
+    selection: Selection,
+    text: &str,
+  ) -> Result<(), arboard::Error> {
+    clipboard.set().clipboard(kind(selection)).text(text)
+  }
+
+  pub fn get(
+    clipboard: &mut Clipboard,
+    selection: Selection,
+  ) -> Result<String, arboard::Error> {
+    clipboard.get().clipboard(kind(selection)).text()
+  }
+
+  pub fn clear(
+    clipboard: &mut Clipboard,
+    selection: Selection,
+  ) -> Result<(), arboard::Error> {
+    clipboard.clear_with().clipboard(kind(selection))
+  }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod ops {
+  use arboard::Clipboard;
+
+  use super::Selection;
+
+  pub fn is_available(selection: Selection) -> bool {
+    selection == Selection::Clipboard
+  }
+
+  pub fn set(
+    clipboard: &mut Clipboard,
+    _selection: Selection,
+    text: &str,
+  ) -> Result<(), arboard::Error> {
+    clipboard.set_text(text)
+  }
+
+  pub fn get(
+    clipboard: &mut Clipboard,
+    _selection: Selection,
+  ) -> Result<String, arboard::Error> {
+    clipboard.get_text()
+  }
+
+  pub fn clear(
+    clipboard: &mut Clipboard,
+    _selection: Selection,
+  ) -> Result<(), arboard::Error> {
+    clipboard.clear()
+  }
+}
+
+impl Backend for System {
+  fn is_available(&self) -> bool {
+    ops::is_available(self.0)
+  }
+
+  fn acquire(&self, key: &str) -> Result<Box<dyn Lease>, Error> {
+    let Ok(mut clipboard) = arboard::Clipboard::new() else {
+      return Err(Error::ClipboardOpFailed);
+    };
+    if ops::set(&mut clipboard, self.0, key).is_err() {
+      return Err(Error::ClipboardOpFailed);
+    }
+    Ok(Box::new(SystemLease {
+      clipboard,
+      selection: self.0,
+      key: key.to_owned(),
+    }))
+  }
+}
+
+/// Holds the clipboard for as long as it contains the copied key.  On X11 the
+/// selection is served for as long as `clipboard` is alive.
+struct SystemLease {
+  clipboard: arboard::Clipboard,
+  selection: Selection,
+  key: String,
+}
+
+impl Lease for SystemLease {
+  fn is_held(&mut self) -> bool {
+    matches!(
+      ops::get(&mut self.clipboard, self.selection),
+      Ok(text) if text == self.key
+    )
+  }
+
+  fn release(mut self: Box<Self>) -> Result<(), Error> {
+    if self.is_held()
+      && ops::clear(&mut self.clipboard, self.selection).is_err()
+    {
+      return Err(Error::ClipboardOpFailed);
+    }
+    Ok(())
+  }
+}
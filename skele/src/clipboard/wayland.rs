@@ -0,0 +1,70 @@
+//! # Wayland clipboard backend
+
+use std::{
+  env,
+  thread::{self, JoinHandle},
+};
+
+use wl_clipboard_rs::copy::{
+  self, ClipboardType, MimeType, Options, Seat, Source,
+};
+
+use super::{Backend, Error, Lease, Selection};
+
+pub struct Wayland(pub Selection);
+
+fn clipboard_type(selection: Selection) -> ClipboardType {
+  match selection {
+    Selection::Clipboard => ClipboardType::Regular,
+    Selection::Primary => ClipboardType::Primary,
+  }
+}
+
+impl Backend for Wayland {
+  fn is_available(&self) -> bool {
+    env::var_os("WAYLAND_DISPLAY").is_some_and(|display| !display.is_empty())
+  }
+
+  fn acquire(&self, key: &str) -> Result<Box<dyn Lease>, Error> {
+    let mut options = Options::new();
+    options
+      .clipboard(clipboard_type(self.0))
+      .foreground(true)
+      .trim_newline(true);
+    let Ok(prepared) = options
+      .prepare_copy(Source::Bytes(key.as_bytes().into()), MimeType::Text)
+    else {
+      return Err(Error::ClipboardOpFailed);
+    };
+    let server = thread::spawn(move || {
+      let _ = prepared.serve();
+    });
+    Ok(Box::new(WaylandLease {
+      selection: self.0,
+      server,
+    }))
+  }
+}
+
+/// Holds the clipboard for as long as the serving thread is alive.  The thread
+/// exits when the selection is taken over or cleared.
+struct WaylandLease {
+  selection: Selection,
+  server: JoinHandle<()>,
+}
+
+impl Lease for WaylandLease {
+  fn is_held(&mut self) -> bool {
+    !self.server.is_finished()
+  }
+
+  fn release(mut self: Box<Self>) -> Result<(), Error> {
+    if self.is_held()
+      && copy::clear(clipboard_type(self.selection), Seat::All).is_err()
+    {
+      return Err(Error::ClipboardOpFailed);
+    }
+    let _ = self.server.join();
+    Ok(())
+  }
+}
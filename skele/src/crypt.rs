@@ -0,0 +1,440 @@
+//! # Encrypted files
+//!
+//! Encrypts small secret files, such as recovery codes and API tokens, with a
+//! key derived from the skeleton key.  Every file gets a key of its own
+//! derived with a random salt, and as the key does not depend on the key
+//! state the file opens wherever the same skeleton key is used.
+//!
+//! The encrypted file is self-describing JSON lines.  The first line is the
+//! header naming the format, the fingerprint of the skeleton key, the salt,
+//! and the chunk size.  Each following line is a chunk of the plain text
+//! sealed with [`Ciphered`].  The associated data of a chunk binds it to the
+//! header, to its place in the file, and to whether it is the last chunk, so
+//! that chunks cannot be swapped, dropped, or added without notice.  A file
+//! of any size is handled one chunk at a time.
+
+use std::{
+  ffi::OsString,
+  fs,
+  io::{self, BufRead, BufReader, BufWriter, Read, Write},
+  os::unix::fs::OpenOptionsExt,
+  path::{Path, PathBuf},
+  process,
+};
+
+use base64ct::Encoding;
+use rand_core::RngCore;
+use rustybones::KeySource;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::{ciphered::Ciphered, serde_helpers::*};
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("i/o error")]
+  Io(#[from] io::Error),
+  #[error("{0} exists already")]
+  Exists(PathBuf),
+  #[error("cannot tell the output path; give it with --output")]
+  NoOutputPath,
+  #[error("not a file encrypted with skele")]
+  Malformed,
+  #[error("encrypted with another skeleton key ({0})")]
+  WrongKey(String),
+  #[error("the file has been tampered with or truncated")]
+  Tampered,
+  #[error("cipher operation failed")]
+  CipherOpFailed,
+}
+
+/// The extension of the encrypted files.
+pub const EXTENSION: &str = "skele";
+
+/// The size of the plain text chunks.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The largest chunk size accepted when decrypting.  This bounds the memory
+/// taken by a file from elsewhere.
+const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+const FILE_KEY_LABEL: &str = "skele/file-key/v1";
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Format {
+  #[serde(rename = "skele/encrypted-file/v1")]
+  V1,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+  format: Format,
+  fingerprint: String,
+  #[serde(serialize_with = "ser_octets", deserialize_with = "de_octet_array")]
+  salt: [u8; 32],
+  chunk_size: usize,
+}
+
+/// Returns the path the file is written to when no output path is given:
+/// the input path with the extension added when encrypting or taken away when
+/// decrypting.
+pub fn output_path(input: &Path, encrypting: bool) -> Result<PathBuf, Error> {
+  if encrypting {
+    let mut path = input.as_os_str().to_owned();
+    path.push(".");
+    path.push(EXTENSION);
+    Ok(path.into())
+  } else if input.extension().is_some_and(|e| e == EXTENSION) {
+    Ok(input.with_extension(""))
+  } else {
+    Err(Error::NoOutputPath)
+  }
+}
+
+/// Encrypts the file at `input` into a new file at `output`.  Refuses to
+/// replace an existing file unless `overwrite` is set.
+pub fn encrypt_file(
+  key_source: &KeySource,
+  fingerprint: &str,
+  input: &Path,
+  output: &Path,
+  overwrite: bool,
+) -> Result<(), Error> {
+  let input = fs::File::open(input)?;
+  write_file(output, overwrite, |writer| {
+    encrypt(key_source, fingerprint, input, writer)
+  })
+}
+
+/// Decrypts the file at `input` into a new file at `output`.  Nothing is left
+/// at `output` unless the whole file decrypts.  Refuses to replace an
+/// existing file unless `overwrite` is set.
+pub fn decrypt_file(
+  key_source: &KeySource,
+  fingerprint: &str,
+  input: &Path,
+  output: &Path,
+  overwrite: bool,
+) -> Result<(), Error> {
+  let input = BufReader::new(fs::File::open(input)?);
+  write_file(output, overwrite, |writer| {
+    decrypt(key_source, fingerprint, input, writer)
+  })
+}
+
+pub fn encrypt(
+  key_source: &KeySource,
+  fingerprint: &str,
+  input: impl Read,
+  output: impl Write,
+) -> Result<(), Error> {
+  encrypt_chunked(key_source, fingerprint, input, output, CHUNK_SIZE)
+}
+
+fn encrypt_chunked(
+  key_source: &KeySource,
+  fingerprint: &str,
+  mut input: impl Read,
+  mut output: impl Write,
+  chunk_size: usize,
+) -> Result<(), Error> {
+  let mut salt = [0u8; 32];
+  rand_core::OsRng.fill_bytes(&mut salt);
+  let header = Header {
+    format: Format::V1,
+    fingerprint: fingerprint.to_owned(),
+    salt,
+    chunk_size,
+  };
+  let header_line = serde_json::to_string(&header).map_err(io::Error::from)?;
+  writeln!(output, "{}", header_line)?;
+  let key = file_key(key_source, &salt);
+  let header_digest = Sha256::digest(header_line.as_bytes());
+
+  let mut chunks = [vec![0u8; chunk_size], vec![0u8; chunk_size]];
+  let result =
+    seal_chunks(&key, &header_digest, &mut input, &mut output, &mut chunks);
+  chunks.iter_mut().for_each(|chunk| chunk.fill(0));
+  result?;
+  output.flush()?;
+  Ok(())
+}
+
+/// Seals the input chunk by chunk reading a chunk ahead to know which chunk is
+/// the last one.  An empty input still gets an empty last chunk.
+fn seal_chunks(
+  key: &[u8],
+  header_digest: &[u8],
+  input: &mut impl Read,
+  output: &mut impl Write,
+  [chunk, next_chunk]: &mut [Vec<u8>; 2],
+) -> Result<(), Error> {
+  let mut len = read_chunk(input, chunk)?;
+  for index in 0.. {
+    let next_len = if len == chunk.len() {
+      read_chunk(input, next_chunk)?
+    } else {
+      0
+    };
+    let last = next_len == 0;
+    let aad = chunk_aad(header_digest, index, last);
+    let sealed = Ciphered::seal(&chunk[..len], key, &aad)
+      .map_err(|_| Error::CipherOpFailed)?;
+    serde_json::to_writer(&mut *output, &sealed).map_err(io::Error::from)?;
+    output.write_all(b"\n")?;
+    if last {
+      break;
+    }
+    std::mem::swap(chunk, next_chunk);
+    len = next_len;
+  }
+  Ok(())
+}
+
+pub fn decrypt(
+  key_source: &KeySource,
+  fingerprint: &str,
+  mut input: impl BufRead,
+  mut output: impl Write,
+) -> Result<(), Error> {
+  let mut line = String::new();
+  if read_line(&mut input, &mut line)? == 0 {
+    return Err(Error::Malformed);
+  }
+  let header_line = line.trim_end_matches('\n');
+  let header: Header =
+    serde_json::from_str(header_line).map_err(|_| Error::Malformed)?;
+  if header.fingerprint != fingerprint {
+    return Err(Error::WrongKey(header.fingerprint));
+  }
+  if header.chunk_size == 0 || header.chunk_size > MAX_CHUNK_SIZE {
+    return Err(Error::Malformed);
+  }
+  let key = file_key(key_source, &header.salt);
+  let header_digest = Sha256::digest(header_line.as_bytes());
+
+  for index in 0.. {
+    if read_line(&mut input, &mut line)? == 0 {
+      return Err(Error::Tampered);
+    }
+    let sealed: Ciphered =
+      serde_json::from_str(&line).map_err(|_| Error::Tampered)?;
+    let (chunk, last) =
+      match sealed.open(&key, &chunk_aad(&header_digest, index, false)) {
+        Ok(chunk) => (chunk, false),
+        Err(_) => {
+          let aad = chunk_aad(&header_digest, index, true);
+          (sealed.open(&key, &aad).map_err(|_| Error::Tampered)?, true)
+        }
+      };
+    if chunk.len() > header.chunk_size {
+      return Err(Error::Tampered);
+    }
+    output.write_all(&chunk)?;
+    if last {
+      break;
+    }
+  }
+  if read_line(&mut input, &mut line)? != 0 {
+    return Err(Error::Tampered);
+  }
+  output.flush()?;
+  Ok(())
+}
+
+/// Derives the key of the file from the skeleton key and the salt of the
+/// file.
+fn file_key(key_source: &KeySource, salt: &[u8; 32]) -> [u8; 32] {
+  let mut key = [0u8; 32];
+  key_source.fill_bytes(
+    FILE_KEY_LABEL,
+    &[&base64ct::Base64::encode_string(salt)],
+    &mut key,
+  );
+  key
+}
+
+/// Returns the associated data of the chunk: the digest of the header line,
+/// the index of the chunk, and whether the chunk is the last one.
+fn chunk_aad(header_digest: &[u8], index: u64, last: bool) -> Vec<u8> {
+  let mut aad = header_digest.to_vec();
+  aad.extend(index.to_be_bytes());
+  aad.push(last as u8);
+  aad
+}
+
+/// Reads until the buffer is full or the input ends and returns the number
+/// of bytes read.
+fn read_chunk(input: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+  let mut len = 0;
+  while len < buffer.len() {
+    match input.read(&mut buffer[len..]) {
+      Ok(0) => break,
+      Ok(n) => len += n,
+      Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+      Err(err) => return Err(err),
+    }
+  }
+  Ok(len)
+}
+
+/// Reads a line of at most the length a chunk of the largest size takes.
+fn read_line(
+  input: &mut impl BufRead,
+  line: &mut String,
+) -> Result<usize, Error> {
+  const MAX_LINE: u64 = 2 * MAX_CHUNK_SIZE as u64;
+  line.clear();
+  match input.take(MAX_LINE).read_line(line) {
+    Ok(len) if len as u64 == MAX_LINE && !line.ends_with('\n') => {
+      Err(Error::Malformed)
+    }
+    Ok(len) => Ok(len),
+    Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+      Err(Error::Malformed)
+    }
+    Err(err) => Err(err.into()),
+  }
+}
+
+/// Writes the file readable by the owner only.  The contents go to a
+/// temporary file next to `path` that replaces `path` only once all is
+/// written.
+pub fn write_file(
+  path: &Path,
+  overwrite: bool,
+  write: impl FnOnce(&mut BufWriter<fs::File>) -> Result<(), Error>,
+) -> Result<(), Error> {
+  if !overwrite && path.exists() {
+    return Err(Error::Exists(path.to_owned()));
+  }
+  let mut name = OsString::from(".");
+  name.push(path.file_name().unwrap_or_default());
+  name.push(format!(".{}.tmp", process::id()));
+  let temporary = path.with_file_name(name);
+  let file = fs::OpenOptions::new()
+    .write(true)
+    .create_new(true)
+    .mode(0o600)
+    .open(&temporary)?;
+  let mut writer = BufWriter::new(file);
+  let result = write(&mut writer).and_then(|()| {
+    let file = writer.into_inner().map_err(|err| err.into_error())?;
+    file.sync_all()?;
+    Ok(fs::rename(&temporary, path)?)
+  });
+  if result.is_err() {
+    let _ = fs::remove_file(&temporary);
+  }
+  result
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  const FINGERPRINT: &str = "Abcd-Efgh";
+
+  fn encrypted(plain: &[u8], chunk_size: usize) -> Vec<u8> {
+    let key_source = KeySource::new("secret skeleton passphrase");
+    let mut sealed = Vec::new();
+    encrypt_chunked(&key_source, FINGERPRINT, plain, &mut sealed, chunk_size)
+      .unwrap();
+    sealed
+  }
+
+  fn decrypted(sealed: &[u8], skeleton_key: &str) -> Result<Vec<u8>, Error> {
+    let key_source = KeySource::new(skeleton_key);
+    let mut plain = Vec::new();
+    decrypt(&key_source, FINGERPRINT, sealed, &mut plain)?;
+    Ok(plain)
+  }
+
+  #[test]
+  fn test_round_trip() {
+    for plain in [&b""[..], b"abc", b"abcdefgh", b"abcdefghijklmnopq"] {
+      let sealed = encrypted(plain, 8);
+      assert_eq!(
+        decrypted(&sealed, "secret skeleton passphrase").unwrap(),
+        plain
+      );
+    }
+    // Every file has a key of its own.
+    assert_ne!(encrypted(b"abc", 8), encrypted(b"abc", 8));
+  }
+
+  #[test]
+  fn test_tampering() {
+    let sealed = encrypted(b"abcdefghijklmnopq", 8);
+    let lines = sealed.split_inclusive(|&b| b == b'\n').collect::<Vec<_>>();
+    assert_eq!(lines.len(), 4);
+    for tampered in [
+      // Dropping the last chunk or all
+      [lines[0], lines[1], lines[2]].concat(),
+      lines[0].to_vec(),
+      // Swapping chunks
+      [lines[0], lines[2], lines[1], lines[3]].concat(),
+      // Adding a chunk after the last one
+      [lines[0], lines[1], lines[2], lines[3], lines[3]].concat(),
+    ] {
+      assert!(matches!(
+        decrypted(&tampered, "secret skeleton passphrase"),
+        Err(Error::Tampered)
+      ));
+    }
+    // Chunks from another file with the same key
+    let other = encrypted(b"abcdefghijklmnopq", 8);
+    let other_lines =
+      other.split_inclusive(|&b| b == b'\n').collect::<Vec<_>>();
+    let mixed = [lines[0], other_lines[1], lines[2], lines[3]].concat();
+    assert!(matches!(
+      decrypted(&mixed, "secret skeleton passphrase"),
+      Err(Error::Tampered)
+    ));
+  }
+
+  #[test]
+  fn test_wrong_key_and_format() {
+    let sealed = encrypted(b"abc", 8);
+    assert!(matches!(
+      decrypted(&sealed, "another skeleton passphrase"),
+      Err(Error::Tampered)
+    ));
+    let mut plain = Vec::new();
+    assert!(matches!(
+      decrypt(
+        &KeySource::new("secret skeleton passphrase"),
+        "Jkmn-Pqrs",
+        &sealed[..],
+        &mut plain
+      ),
+      Err(Error::WrongKey(fingerprint)) if fingerprint == FINGERPRINT
+    ));
+    assert!(matches!(
+      decrypted(b"plain text\n", "secret skeleton passphrase"),
+      Err(Error::Malformed)
+    ));
+    assert!(matches!(
+      decrypted(b"", "secret skeleton passphrase"),
+      Err(Error::Malformed)
+    ));
+  }
+
+  #[test]
+  fn test_output_path() {
+    assert_eq!(
+      output_path(Path::new("codes.txt"), true).unwrap(),
+      Path::new("codes.txt.skele")
+    );
+    assert_eq!(
+      output_path(Path::new("codes.txt.skele"), false).unwrap(),
+      Path::new("codes.txt")
+    );
+    assert!(matches!(
+      output_path(Path::new("codes.txt"), false),
+      Err(Error::NoOutputPath)
+    ));
+  }
+}
@@ -0,0 +1,46 @@
+//! # Emitting keys to external programs
+//!
+//! Pipes the key to a configurable program through its standard input, never
+//! on the command line.  This is meant for programs that type the key out
+//! (e.g. `xdotool type --file -` or `ydotool type --file -`) for the fields
+//! that do not allow pasting.
+
+use std::{
+  io::{self, Write},
+  process::{Command, Stdio},
+};
+
+use thiserror::Error;
+
+use crate::clipboard::split_command_line;
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("i/o error")]
+  Io(#[from] io::Error),
+  #[error("no command given")]
+  NoCommand,
+  #[error("command failed")]
+  CommandFailed,
+}
+
+/// Runs the command line with the key written to its standard input.
+pub fn emit(key: &str, command_line: &str) -> Result<(), Error> {
+  let argv = split_command_line(command_line);
+  let Some((program, args)) = argv.split_first() else {
+    return Err(Error::NoCommand);
+  };
+  let mut child = Command::new(program)
+    .args(args)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::null())
+    .spawn()?;
+  if let Some(mut stdin) = child.stdin.take() {
+    stdin.write_all(key.as_bytes())?;
+  }
+  if child.wait()?.success() {
+    Ok(())
+  } else {
+    Err(Error::CommandFailed)
+  }
+}
@@ -0,0 +1,132 @@
+//! # Fuzzy search over stored credentials
+
+use crate::state::Credentials;
+
+const MATCH_SCORE: i64 = 16;
+const CONSECUTIVE_BONUS: i64 = 16;
+const WORD_START_BONUS: i64 = 24;
+const GAP_PENALTY: i64 = 1;
+
+/// Scores how well `pattern` matches `candidate` as a case-insensitive
+/// subsequence.  Returns `None` when not all characters of the pattern can be
+/// found in order.  Consecutive matches and matches at the start of a word are
+/// favoured; gaps between matched characters are penalized.
+pub fn score(pattern: &str, candidate: &str) -> Option<i64> {
+  let mut pattern = pattern
+    .chars()
+    .filter(|c| !c.is_whitespace())
+    .flat_map(char::to_lowercase)
+    .peekable();
+  let mut score = 0;
+  let mut prev: Option<char> = None;
+  let mut prev_matched = false;
+  let mut gap = 0;
+  let mut started = false;
+  for c in candidate.chars() {
+    let Some(&p) = pattern.peek() else {
+      break;
+    };
+    let word_start = prev.is_none_or(|prev| !prev.is_alphanumeric());
+    if c.to_lowercase().eq(p.to_lowercase()) {
+      pattern.next();
+      score += MATCH_SCORE;
+      if prev_matched {
+        score += CONSECUTIVE_BONUS;
+      }
+      if word_start {
+        score += WORD_START_BONUS;
+      }
+      if started {
+        score -= gap * GAP_PENALTY;
+      }
+      started = true;
+      prev_matched = true;
+      gap = 0;
+    } else {
+      prev_matched = false;
+      gap += 1;
+    }
+    prev = Some(c);
+  }
+  if pattern.peek().is_none() {
+    Some(score)
+  } else {
+    None
+  }
+}
+
+/// Weight given to how often and how recently the credentials have been used.
+/// `now` is in seconds since the Unix epoch.
+fn usage_weight(credentials: &Credentials, now: u64) -> i64 {
+  (8.0 * credentials.frecency(now).ln_1p()) as i64
+}
+
+/// Returns the credentials matching `pattern` ordered from the best match to
+/// the worst.  The ties are broken by the label of the credentials.
+pub fn search<'a, I>(
+  pattern: &str,
+  credentials: I,
+  now: u64,
+) -> Vec<&'a Credentials>
+where
+  I: IntoIterator<Item = &'a Credentials>,
+{
+  let mut matches = credentials
+    .into_iter()
+    .filter_map(|c| {
+      score(pattern, &c.label()).map(|s| (s + usage_weight(c, now), c))
+    })
+    .collect::<Vec<_>>();
+  matches.sort_by(|(l_score, l), (r_score, r)| {
+    r_score.cmp(l_score).then_with(|| l.label().cmp(&r.label()))
+  });
+  matches.into_iter().map(|(_, c)| c).collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn credentials(domain: &str, identity: &str, count: u32) -> Credentials {
+    let mut c = Credentials::new(domain, identity);
+    for _ in 0..count {
+      c.record_use(0);
+    }
+    c
+  }
+
+  #[test]
+  fn test_score_requires_subsequence() {
+    assert!(score("gh", "alice @ github.com").is_some());
+    assert!(score("GH", "alice @ github.com").is_some());
+    assert!(score("hg", "alice @ github.com").is_none());
+    assert!(score("xyz", "alice @ github.com").is_none());
+    assert_eq!(score("", "alice @ github.com"), Some(0));
+  }
+
+  #[test]
+  fn test_score_prefers_consecutive_and_word_starts() {
+    let tight = score("git", "alice @ github.com").unwrap();
+    let loose = score("git", "alice @ gmail.it").unwrap();
+    assert!(tight > loose);
+    let word = score("bo", "bob @ example.com").unwrap();
+    let inner = score("bo", "alice @ jumbo.com").unwrap();
+    assert!(word > inner);
+  }
+
+  #[test]
+  fn test_search_ranks_by_score_and_usage() {
+    let all = [
+      credentials("gitlab.com", "alice", 1),
+      credentials("github.com", "alice", 1),
+      credentials("github.com", "bob", 40),
+      credentials("example.com", "carol", 100),
+    ];
+    let found = search("gith", &all, 0)
+      .into_iter()
+      .map(|c| c.label())
+      .collect::<Vec<_>>();
+    assert_eq!(found, vec!["bob @ github.com", "alice @ github.com"]);
+  }
+}
@@ -0,0 +1,427 @@
+//! # Importing from other password managers
+//!
+//! Reads the exports of other password managers into entries of domain,
+//! identity, password, and notes.  The CSV exports of Bitwarden, KeePassXC,
+//! and 1Password are told apart by their header rows, which name the columns.
+//! Bitwarden's JSON export is read as well.  A `pass` store is read by asking
+//! `gpg` to decrypt each file into memory.  Nothing is ever written to disk.
+
+use std::{
+  fs, io,
+  path::{Path, PathBuf},
+  process::{Command, Stdio},
+};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use crate::native_host::url_host;
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("i/o error")]
+  Io(#[from] io::Error),
+  #[error("malformed export: {0}")]
+  Malformed(String),
+  #[error("failed to decrypt {0}")]
+  Gpg(PathBuf),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+  /// Bitwarden JSON or CSV export
+  Bitwarden,
+  /// KeePassXC CSV export
+  Keepassxc,
+  /// 1Password CSV export
+  #[value(name = "1password")]
+  OnePassword,
+  /// Directory tree of a pass password store
+  Pass,
+}
+
+/// An account read from an export.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Entry {
+  /// The name of the entry in the password manager.
+  pub title: String,
+  pub domain: Option<String>,
+  pub identity: Option<String>,
+  /// Zeroed when dropped.
+  pub password: Option<Zeroizing<String>>,
+  pub notes: Option<String>,
+}
+
+/// Reads the entries from the export at `path`.
+pub fn read(format: Format, path: &Path) -> Result<Vec<Entry>, Error> {
+  match format {
+    Format::Bitwarden => {
+      let text = Zeroizing::new(fs::read_to_string(path)?);
+      if text.trim_start().starts_with('{') {
+        from_bitwarden_json(&text)
+      } else {
+        from_csv(&text)
+      }
+    }
+    Format::Keepassxc | Format::OnePassword => {
+      from_csv(&Zeroizing::new(fs::read_to_string(path)?))
+    }
+    Format::Pass => from_pass_store(path),
+  }
+}
+
+/// The names the columns of the CSV exports go by, in lowercase.
+const TITLE_COLUMNS: &[&str] = &["title", "name"];
+const URL_COLUMNS: &[&str] = &["url", "website", "login_uri", "urls"];
+const IDENTITY_COLUMNS: &[&str] = &["username", "login_username"];
+const PASSWORD_COLUMNS: &[&str] = &["password", "login_password"];
+const NOTES_COLUMNS: &[&str] = &["notes", "notesplain"];
+
+fn from_csv(text: &str) -> Result<Vec<Entry>, Error> {
+  let mut rows = parse_csv(text.trim_start_matches('\u{feff}'))?.into_iter();
+  let Some(header) = rows.next() else {
+    return Ok(Vec::new());
+  };
+  let column = |names: &[&str]| {
+    header
+      .iter()
+      .position(|h| names.contains(&h.trim().to_lowercase().as_str()))
+  };
+  let (Some(identity), Some(password)) =
+    (column(IDENTITY_COLUMNS), column(PASSWORD_COLUMNS))
+  else {
+    return Err(Error::Malformed(
+      "no username and password columns".to_owned(),
+    ));
+  };
+  let (title, url, notes) = (
+    column(TITLE_COLUMNS),
+    column(URL_COLUMNS),
+    column(NOTES_COLUMNS),
+  );
+  Ok(
+    rows
+      .filter(|row| row.iter().any(|field| !field.is_empty()))
+      .map(|row| {
+        let field = |index: Option<usize>| {
+          index
+            .and_then(|i| row.get(i))
+            .filter(|field| !field.is_empty())
+            .cloned()
+        };
+        entry(
+          field(title).unwrap_or_default(),
+          field(url).as_deref(),
+          field(Some(identity)),
+          field(Some(password)),
+          field(notes),
+        )
+      })
+      .collect(),
+  )
+}
+
+#[derive(Deserialize)]
+struct BitwardenExport {
+  #[serde(default)]
+  encrypted: bool,
+  items: Vec<BitwardenItem>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenItem {
+  name: String,
+  notes: Option<String>,
+  login: Option<BitwardenLogin>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenLogin {
+  username: Option<String>,
+  password: Option<String>,
+  #[serde(default)]
+  uris: Option<Vec<BitwardenUri>>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenUri {
+  uri: Option<String>,
+}
+
+fn from_bitwarden_json(text: &str) -> Result<Vec<Entry>, Error> {
+  let export: BitwardenExport = serde_json::from_str(text)
+    .map_err(|err| Error::Malformed(err.to_string()))?;
+  if export.encrypted {
+    return Err(Error::Malformed(
+      "encrypted exports are not supported".to_owned(),
+    ));
+  }
+  Ok(
+    export
+      .items
+      .into_iter()
+      // Only logins have credentials.
+      .filter_map(|item| {
+        let login = item.login?;
+        let url = login
+          .uris
+          .into_iter()
+          .flatten()
+          .find_map(|uri| uri.uri.filter(|uri| !uri.is_empty()));
+        Some(entry(
+          item.name,
+          url.as_deref(),
+          login.username,
+          login.password,
+          item.notes,
+        ))
+      })
+      .collect(),
+  )
+}
+
+/// Makes an entry taking the domain from the URL, or from the title if it
+/// looks like a domain.
+fn entry(
+  title: String,
+  url: Option<&str>,
+  identity: Option<String>,
+  password: Option<String>,
+  notes: Option<String>,
+) -> Entry {
+  let domain = url
+    .and_then(url_host)
+    .or_else(|| {
+      let title = title.trim().to_lowercase();
+      (title.contains('.') && !title.contains(char::is_whitespace))
+        .then_some(title)
+    })
+    .map(|host| host.strip_prefix("www.").unwrap_or(&host).to_owned());
+  Entry {
+    title,
+    domain,
+    identity: identity.filter(|identity| !identity.is_empty()),
+    password: password
+      .filter(|password| !password.is_empty())
+      .map(Zeroizing::new),
+    notes: notes.filter(|notes| !notes.trim().is_empty()),
+  }
+}
+
+/// Reads the `.gpg` files of the store.  The path of a file relative to the
+/// store names the entry, e.g. `github.com/alice.gpg` is the identity `alice`
+/// of the domain `github.com`.
+fn from_pass_store(store: &Path) -> Result<Vec<Entry>, Error> {
+  let mut files = Vec::new();
+  find_gpg_files(store, &mut files)?;
+  files.sort();
+  let mut entries = Vec::new();
+  for file in files {
+    let output = Command::new("gpg")
+      .args(["--quiet", "--batch", "--decrypt"])
+      .arg(&file)
+      .stdin(Stdio::null())
+      .stderr(Stdio::inherit())
+      .output()?;
+    if !output.status.success() {
+      return Err(Error::Gpg(file));
+    }
+    let plain = Zeroizing::new(output.stdout);
+    let name = file
+      .strip_prefix(store)
+      .unwrap_or(&file)
+      .with_extension("")
+      .to_string_lossy()
+      .into_owned();
+    let parsed = match std::str::from_utf8(&plain) {
+      Ok(text) => Ok(from_pass_entry(&name, text)),
+      Err(_) => Err(Error::Malformed(format!("{} is not text", name))),
+    };
+    entries.push(parsed?);
+  }
+  Ok(entries)
+}
+
+fn find_gpg_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+  for entry in fs::read_dir(dir)? {
+    let entry = entry?;
+    let path = entry.path();
+    let hidden = entry.file_name().to_string_lossy().starts_with('.');
+    if entry.file_type()?.is_dir() && !hidden {
+      find_gpg_files(&path, files)?;
+    } else if path.extension().is_some_and(|e| e == "gpg") {
+      files.push(path);
+    }
+  }
+  Ok(())
+}
+
+/// Parses a decrypted pass entry.  The first line is the password and the
+/// following lines may give the identity and the URL with the usual
+/// `login:`, `username:`, `user:`, and `url:` keys; the rest are notes.
+fn from_pass_entry(name: &str, text: &str) -> Entry {
+  let mut lines = text.lines();
+  let password = lines.next().map(str::to_owned);
+  let (mut identity, mut url, mut notes) = (None, None, Vec::new());
+  for line in lines {
+    match line.split_once(':') {
+      Some((key, value))
+        if ["login", "username", "user"]
+          .contains(&key.trim().to_lowercase().as_str()) =>
+      {
+        identity = Some(value.trim().to_owned());
+      }
+      Some((key, value)) if key.trim().eq_ignore_ascii_case("url") => {
+        url = Some(value.trim().to_owned());
+      }
+      _ => notes.push(line),
+    }
+  }
+  // The last component names the identity unless given in the file and the
+  // one before it the domain, e.g. `web/github.com/alice`.
+  let mut components = name.rsplit('/');
+  let last = components.next().unwrap_or_default();
+  let (domain_name, identity) = match (identity, components.next()) {
+    (Some(identity), _) => (last, Some(identity)),
+    (None, Some(parent)) => (parent, Some(last.to_owned())),
+    (None, None) => (last, None),
+  };
+  let mut entry = entry(
+    domain_name.to_owned(),
+    url.as_deref(),
+    identity,
+    password,
+    Some(notes.join("\n")),
+  );
+  entry.title = name.to_owned();
+  entry
+}
+
+/// Parses CSV as in RFC 4180: fields are separated by commas, and quoted
+/// fields may contain commas, line breaks, and quotes doubled.
+fn parse_csv(text: &str) -> Result<Vec<Vec<String>>, Error> {
+  let mut rows = Vec::new();
+  let mut row = Vec::new();
+  let mut field = String::new();
+  let mut chars = text.chars().peekable();
+  let mut quoted = false;
+  while let Some(c) = chars.next() {
+    match (quoted, c) {
+      (true, '"') if chars.peek() == Some(&'"') => {
+        chars.next();
+        field.push('"');
+      }
+      (true, '"') => quoted = false,
+      (true, c) => field.push(c),
+      (false, '"') if field.is_empty() => quoted = true,
+      (false, ',') => row.push(std::mem::take(&mut field)),
+      (false, '\r') if chars.peek() == Some(&'\n') => {}
+      (false, '\n') => {
+        row.push(std::mem::take(&mut field));
+        rows.push(std::mem::take(&mut row));
+      }
+      (false, c) => field.push(c),
+    }
+  }
+  if quoted {
+    return Err(Error::Malformed("unterminated quoted field".to_owned()));
+  }
+  if !field.is_empty() || !row.is_empty() {
+    row.push(field);
+    rows.push(row);
+  }
+  Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn test_parse_csv() {
+    let rows = parse_csv("a,\"b,c\",\"d \"\"e\"\"\"\r\n\"f\ng\",,h\n").unwrap();
+    assert_eq!(rows, [vec!["a", "b,c", "d \"e\""], vec!["f\ng", "", "h"]]);
+    assert!(parse_csv("a,\"b").is_err());
+  }
+
+  #[test]
+  fn test_csv_exports() {
+    let keepassxc = "\"Group\",\"Title\",\"Username\",\"Password\",\"URL\",\
+                     \"Notes\",\"TOTP\"\n\"Root\",\"GitHub\",\"alice\",\"s3cret\",\
+                     \"https://www.github.com/login\",\"\",\"\"\n";
+    let bitwarden = "folder,favorite,type,name,notes,fields,reprompt,\
+                     login_uri,login_username,login_password,login_totp\n,,\
+                     login,github.com,Work,,0,,alice,s3cret,\n";
+    let one_password = "Title,Url,Username,Password,OTPAuth,Favorite,\
+                        Archived,Tags,Notes\nWiki,https://wiki.example.com,\
+                        bob,,,false,false,,\n";
+    assert_eq!(
+      from_csv(keepassxc).unwrap(),
+      [Entry {
+        title: "GitHub".to_owned(),
+        domain: Some("github.com".to_owned()),
+        identity: Some("alice".to_owned()),
+        password: Some(Zeroizing::new("s3cret".to_owned())),
+        notes: None,
+      }]
+    );
+    assert_eq!(
+      from_csv(bitwarden).unwrap(),
+      [Entry {
+        title: "github.com".to_owned(),
+        domain: Some("github.com".to_owned()),
+        identity: Some("alice".to_owned()),
+        password: Some(Zeroizing::new("s3cret".to_owned())),
+        notes: Some("Work".to_owned()),
+      }]
+    );
+    assert_eq!(
+      from_csv(one_password).unwrap(),
+      [Entry {
+        title: "Wiki".to_owned(),
+        domain: Some("wiki.example.com".to_owned()),
+        identity: Some("bob".to_owned()),
+        password: None,
+        notes: None,
+      }]
+    );
+    assert!(matches!(from_csv("a,b\n1,2\n"), Err(Error::Malformed(_))));
+  }
+
+  #[test]
+  fn test_bitwarden_json() {
+    let json = r#"{"encrypted":false,"items":[
+      {"type":1,"name":"GitHub","notes":null,"login":{"username":"alice",
+        "password":"s3cret","uris":[{"match":null,"uri":"https://github.com"}]}},
+      {"type":2,"name":"Secure note","notes":"text","secureNote":{"type":0}}
+    ]}"#;
+    let entries = from_bitwarden_json(json).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].domain.as_deref(), Some("github.com"));
+    assert_eq!(entries[0].identity.as_deref(), Some("alice"));
+    assert!(from_bitwarden_json(r#"{"encrypted":true,"items":[]}"#).is_err());
+  }
+
+  #[test]
+  fn test_pass_entry() {
+    let entry = from_pass_entry("web/github.com/alice", "s3cret\nrecovery\n");
+    assert_eq!(entry.domain.as_deref(), Some("github.com"));
+    assert_eq!(entry.identity.as_deref(), Some("alice"));
+    assert_eq!(
+      entry.password.as_deref().map(String::as_str),
+      Some("s3cret")
+    );
+    assert_eq!(entry.notes.as_deref(), Some("recovery"));
+    let entry = from_pass_entry(
+      "GitHub",
+      "s3cret\nlogin: bob\nurl: https://github.com/login\n",
+    );
+    assert_eq!(entry.domain.as_deref(), Some("github.com"));
+    assert_eq!(entry.identity.as_deref(), Some("bob"));
+    assert_eq!(entry.notes, None);
+    assert_eq!(from_pass_entry("github.com", "s3cret\n").identity, None);
+  }
+}
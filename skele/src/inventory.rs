@@ -0,0 +1,177 @@
+//! # Credential inventory
+//!
+//! Lists the known credentials for audits: which accounts exist, how they are
+//! tagged, which generation and policy they are on, and when they were last
+//! used.  The passwords are left out unless asked for.  The inventory is
+//! written as CSV with a header row or as a JSON array of objects with the
+//! same fields.
+//!
+//! In CSV the fields that start like a formula, e.g. `=1+2`, get a `'` in
+//! front so that spreadsheets do not run them, except for the passwords,
+//! which are written as they are.  Open an inventory with passwords in a
+//! spreadsheet with care.
+
+use clap::ValueEnum;
+use rustybones::Policy;
+use serde::Serialize;
+use zeroize::Zeroizing;
+
+use crate::serde_helpers::ser_display;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+  Csv,
+  Json,
+}
+
+/// A row of the inventory.
+#[derive(Debug, Serialize)]
+pub struct Row {
+  pub domain: String,
+  pub identity: String,
+  pub tags: Vec<String>,
+  pub generation: usize,
+  /// The time of the last use in seconds since the Unix epoch or zero if
+  /// never used.
+  pub last_used: u64,
+  #[serde(serialize_with = "ser_display")]
+  pub policy: Policy,
+  /// Whether a stored password is in use instead of the derived one.
+  pub stored: bool,
+  /// Zeroed when dropped.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub password: Option<Zeroizing<String>>,
+}
+
+/// Renders the rows in the format.  The password column is there iff the
+/// rows have passwords, which is why the result is zeroed when dropped.
+pub fn render(rows: &[Row], format: Format) -> Zeroizing<String> {
+  match format {
+    Format::Csv => to_csv(rows),
+    Format::Json => {
+      let mut json = Zeroizing::new(
+        serde_json::to_string_pretty(rows).expect("rows serialize"),
+      );
+      json.push('\n');
+      json
+    }
+  }
+}
+
+/// The separator of the tags within the tags column of CSV.
+const TAG_SEPARATOR: &str = ";";
+
+fn to_csv(rows: &[Row]) -> Zeroizing<String> {
+  let with_passwords = rows.iter().any(|row| row.password.is_some());
+  let mut header = vec![
+    "domain",
+    "identity",
+    "tags",
+    "generation",
+    "last_used",
+    "policy",
+    "stored",
+  ];
+  if with_passwords {
+    header.push("password");
+  }
+  let mut csv = Zeroizing::new(header.join(",") + "\r\n");
+  for row in rows {
+    let fields = [
+      row.domain.clone(),
+      row.identity.clone(),
+      row.tags.join(TAG_SEPARATOR),
+      row.generation.to_string(),
+      row.last_used.to_string(),
+      row.policy.to_string(),
+      row.stored.to_string(),
+    ];
+    for (column, field) in fields.iter().enumerate() {
+      if column > 0 {
+        csv.push(',');
+      }
+      push_csv_field(&mut csv, field, true);
+    }
+    // The password goes straight to the output to leave no copies behind.
+    if with_passwords {
+      csv.push(',');
+      let password = row.password.as_ref().map_or("", |p| p.as_str());
+      push_csv_field(&mut csv, password, false);
+    }
+    csv.push_str("\r\n");
+  }
+  csv
+}
+
+/// Appends the field to the CSV quoting it if it has commas, quotes, or line
+/// breaks or, with `defuse`, if it starts with a character that makes
+/// spreadsheets take it as a formula.
+fn push_csv_field(csv: &mut String, field: &str, defuse: bool) {
+  let formula = defuse && field.starts_with(['=', '+', '-', '@']);
+  if formula || field.contains([',', '"', '\r', '\n']) {
+    csv.push('"');
+    if formula {
+      csv.push('\'');
+    }
+    for c in field.chars() {
+      if c == '"' {
+        csv.push('"');
+      }
+      csv.push(c);
+    }
+    csv.push('"');
+  } else {
+    csv.push_str(field);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn row(password: Option<&str>) -> Row {
+    Row {
+      domain: "example.com".to_owned(),
+      identity: "alice, \"admin\"".to_owned(),
+      tags: vec!["work".to_owned(), "shared".to_owned()],
+      generation: 1,
+      last_used: 1700000000,
+      policy: "words".parse().unwrap(),
+      stored: false,
+      password: password.map(|password| Zeroizing::new(password.to_owned())),
+    }
+  }
+
+  #[test]
+  fn test_csv() {
+    assert_eq!(
+      *render(&[row(None)], Format::Csv),
+      "domain,identity,tags,generation,last_used,policy,stored\r\nexample.com,\
+       \"alice, \"\"admin\"\"\",work;shared,1,1700000000,words:bip39:7:-,\
+       false\r\n"
+    );
+    // Formulas are defused but the passwords are kept as they are.
+    let mut formula = row(Some("=1+2"));
+    formula.domain = "=cmd".to_owned();
+    let csv = render(&[formula], Format::Csv);
+    assert!(csv.starts_with(
+      "domain,identity,tags,generation,last_used,policy,stored,password\r\n\"'\
+       =cmd\","
+    ));
+    assert!(csv.ends_with(",false,=1+2\r\n"));
+    let csv = render(&[row(Some("-a,\"b\""))], Format::Csv);
+    assert!(csv.ends_with(",false,\"-a,\"\"b\"\"\"\r\n"));
+  }
+
+  #[test]
+  fn test_json() {
+    let json = render(&[row(None)], Format::Json);
+    assert!(!json.contains("password"));
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value[0]["tags"][1], "shared");
+    assert_eq!(value[0]["policy"], "words:bip39:7:-");
+    let json = render(&[row(Some("s3cret"))], Format::Json);
+    assert!(json.contains("\"password\": \"s3cret\""));
+  }
+}
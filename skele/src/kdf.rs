@@ -39,7 +39,7 @@ impl Default for Kdf {
 }
 
 impl Kdf {
-  fn argon2(&self) -> Result<argon2::Argon2, Error> {
+  fn argon2(&self) -> Result<argon2::Argon2<'_>, Error> {
     if let Ok(params) =
       argon2::Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
     {
@@ -1,14 +1,40 @@
-use std::cmp::Ordering;
+use std::{
+  io::{self, Write},
+  path::{Path, PathBuf},
+  process,
+  time::Duration,
+};
 
-use requestty::{prompt_one, ExpandItem, OnEsc, Question};
-use requestty_utils::{answer, Answer};
+use clap::Parser;
+use record::{
+  FileOperation, ImportState, NoteState, PasswordCheck, QuestionState, Service,
+  ServiceState, StoredPasswordState,
+};
+use requestty::{ExpandItem, ListItem, OnEsc, Question};
+use requestty_utils::{answer, prompt_one, Answer};
 use rustybones::*;
+use zeroize::Zeroizing;
 
+mod agent;
+mod api;
+mod breach;
 mod ciphered;
+mod cli;
 mod clipboard;
+mod crypt;
+mod emit;
+mod fuzzy;
+mod import;
+mod inventory;
 mod kdf;
+mod native_host;
+mod notes;
+mod record;
 mod serde_helpers;
+mod ssh;
 mod state;
+mod totp;
+mod tui;
 mod types;
 
 use types::Passphrase;
@@ -31,23 +57,1234 @@ impl KeyContainer {
   pub fn key_source(&self) -> KeySource {
     KeySource::new(self.skeleton_key.as_str())
   }
+
+  /// Returns the password policy of the domain-identity pair.
+  pub fn policy(&self, domain: &str, identity: &str) -> Policy {
+    self.state.policy(domain, identity)
+  }
+
+  /// Returns the derived one-time password generator of the domain-identity
+  /// pair.
+  pub fn totp(&self, domain: &str, identity: &str) -> totp::Totp {
+    totp::Totp::new(&self.key_source().totp_seed(domain, identity))
+  }
+
+  /// Returns the OpenSSH key pair derived for the domain-identity pair and
+  /// the purpose.
+  pub fn ssh_key(
+    &self,
+    domain: &str,
+    identity: &str,
+    purpose: &str,
+    comment: &str,
+  ) -> Result<ssh_key::PrivateKey, ssh::Error> {
+    ssh::private_key(
+      &self.key_source().ed25519_seed(domain, identity, purpose),
+      comment,
+    )
+  }
+
+  /// Returns the answer to the security question of the domain-identity
+  /// pair.
+  pub fn security_answer(
+    &self,
+    domain: &str,
+    identity: &str,
+    question: &str,
+  ) -> String {
+    self
+      .key_source()
+      .security_answer(domain, identity, question)
+  }
+
+  /// Returns the one-time password generators of the secrets issued for the
+  /// domain-identity pair along with their labels.
+  pub fn imported_totps(
+    &self,
+    domain: &str,
+    identity: &str,
+  ) -> Vec<(String, totp::Totp)> {
+    self.state.find(domain, identity).map_or(Vec::new(), |c| {
+      // The URIs were checked when imported.
+      c.totp_uris
+        .iter()
+        .filter_map(|uri| totp::Totp::from_uri(uri).ok())
+        .collect()
+    })
+  }
+
+  /// Returns the password of the domain-identity pair.  The `generation` picks
+  /// which one of the successive keys is used.  A stored password takes the
+  /// place of the derived ones regardless of the generation.
+  pub fn password(
+    &self,
+    domain: &str,
+    identity: &str,
+    generation: usize,
+  ) -> String {
+    if let Some(password) = self.state.stored_password(domain, identity) {
+      return password.to_owned();
+    }
+    // XXX(soija) TOOD: Get rid of this legacy key source.
+    self.policy(domain, identity).password(
+      &self.key_source(),
+      domain,
+      identity,
+      generation,
+    )
+  }
 }
 
 fn main() {
-  run().unwrap()
+  let cli = cli::Cli::parse();
+  match run(&cli) {
+    Ok(()) => {}
+    Err(err) if cli.format == cli::Format::Json => {
+      let _ = record::Record::error(&err).print();
+      process::exit(1);
+    }
+    Err(err) => {
+      out::use_stderr();
+      let _ = out::error(&format!("{:#}", err));
+      process::exit(1);
+    }
+  }
 }
 
-fn run() -> anyhow::Result<()> {
+fn run(cli: &cli::Cli) -> anyhow::Result<()> {
+  // The native messaging host owns the standard output.
+  if let Some(cli::Command::NativeHost { subdomains, .. }) = &cli.command {
+    native_host::serve(&agent_socket_path(cli)?, *subdomains)?;
+    return Ok(());
+  }
+  if cli.format == cli::Format::Json {
+    out::use_stderr();
+  }
   out::show_notice()?;
   let app_state = state::AppState::try_new()?;
-  while let Some(mut key_container) = ask_skeleton_key(&app_state)? {
-    if !domain_identity_loop(&app_state, &mut key_container)? {
-      break;
+  match &cli.command {
+    None => {
+      if cli.format == cli::Format::Json {
+        return Err(record::Error::Unsupported("interactive mode").into());
+      }
+      while let Some(mut key_container) = ask_skeleton_key(&app_state)? {
+        if !domain_identity_loop(cli, &app_state, &mut key_container)? {
+          break;
+        }
+      }
+    }
+    Some(cli::Command::Agent { idle_timeout }) => {
+      let socket_path = agent_socket_path(cli)?;
+      let Some(key_container) = ask_skeleton_key(&app_state)? else {
+        return aborted(cli);
+      };
+      let agent = agent::Agent::new(
+        &app_state,
+        key_container,
+        Duration::from_secs(*idle_timeout),
+      );
+      report_status(
+        cli,
+        Service::Agent,
+        ServiceState::Listening,
+        &socket_path,
+      )?;
+      agent.serve(&socket_path)?;
+      report_status(cli, Service::Agent, ServiceState::Locked, &socket_path)?;
+    }
+    Some(cli::Command::Api { socket }) => {
+      let socket_path = match socket {
+        Some(path) => path.clone(),
+        None => api::default_socket_path()?,
+      };
+      let Some(key_container) = ask_skeleton_key(&app_state)? else {
+        return aborted(cli);
+      };
+      let server =
+        api::Server::new(&app_state, key_container, confirm_api_call);
+      report_status(cli, Service::Api, ServiceState::Listening, &socket_path)?;
+      out::info(
+        "API",
+        &format!(
+          "session token in {}",
+          api::token_path(&socket_path).display()
+        ),
+      )?;
+      server.serve(&socket_path)?;
+      report_status(cli, Service::Api, ServiceState::Stopped, &socket_path)?;
+    }
+    Some(cli::Command::Tui { lock_timeout }) => {
+      if cli.format == cli::Format::Json {
+        return Err(record::Error::Unsupported("tui").into());
+      }
+      if let Some(key_container) = ask_skeleton_key(&app_state)? {
+        tui::run(
+          &app_state,
+          key_container,
+          tui::Options {
+            lock_timeout: Duration::from_secs(*lock_timeout),
+            clipboard: cli.clipboard_options(),
+          },
+        )?;
+      }
+    }
+    Some(cli::Command::Policy {
+      domain,
+      identity,
+      policy,
+      min_entropy,
+      allow_weak,
+    }) => {
+      if let Some(policy) = policy {
+        if policy.entropy() < f64::from(*min_entropy) && !allow_weak {
+          return Err(
+            cli::Error::WeakPolicy(policy.entropy(), *min_entropy).into(),
+          );
+        }
+      }
+      let Some(mut key_container) = ask_skeleton_key(&app_state)? else {
+        return aborted(cli);
+      };
+      if let Some(policy) = policy {
+        app_state.update_key_state(&mut key_container.state, |state| {
+          state.set_policy(domain, identity, *policy)
+        })?;
+      }
+      let policy = key_container.policy(domain, identity);
+      out::info(
+        "Policy",
+        &format!("{} ({:.1} bits of entropy)", policy, policy.entropy()),
+      )?;
+      if policy.entropy() < f64::from(*min_entropy) {
+        out::warn(
+          "Policy",
+          &format!("weaker than the minimum of {} bits", min_entropy),
+        )?;
+      }
+      if key_container
+        .state
+        .stored_password(domain, identity)
+        .is_some()
+      {
+        out::warn("Policy", "not in use while a password is stored")?;
+      }
+      if cli.format == cli::Format::Json {
+        record::Record::Credentials {
+          domain: domain.clone(),
+          identity: identity.clone(),
+          generation: key_container.state.generation(domain, identity),
+          fingerprint: key_container.state.public.fingerprint.to_string(),
+          policy,
+          password: None,
+        }
+        .print()?;
+      }
+    }
+    Some(cli::Command::Tags {
+      domain,
+      identity,
+      tags,
+      clear,
+    }) => {
+      let Some(mut key_container) = ask_skeleton_key(&app_state)? else {
+        return aborted(cli);
+      };
+      if *clear || !tags.is_empty() {
+        app_state.update_key_state(&mut key_container.state, |state| {
+          state.set_tags(domain, identity, tags)
+        })?;
+      }
+      let tags = key_container
+        .state
+        .tags(domain, identity)
+        .iter()
+        .map(|tag| tag.to_string())
+        .collect::<Vec<_>>();
+      if tags.is_empty() {
+        out::info("Tags", "none")?;
+      } else {
+        out::info("Tags", &tags.join(", "))?;
+      }
+      if cli.format == cli::Format::Json {
+        record::Record::Tags {
+          domain: domain.clone(),
+          identity: identity.clone(),
+          tags,
+        }
+        .print()?;
+      }
     }
+    Some(cli::Command::Export {
+      layout,
+      output,
+      with_passwords,
+      force,
+    }) => {
+      let Some(key_container) = ask_skeleton_key(&app_state)? else {
+        return aborted(cli);
+      };
+      if *with_passwords {
+        let question =
+          format!("Write the passwords in plain text to {}", output.display());
+        if confirm(&question)? != Some(true) {
+          return aborted(cli);
+        }
+      }
+      let rows = key_container
+        .state
+        .secret
+        .credentials
+        .iter()
+        .map(|c| inventory::Row {
+          domain: c.domain.to_string(),
+          identity: c.identity.to_string(),
+          tags: c.tags.iter().map(|tag| tag.to_string()).collect(),
+          generation: c.generation,
+          last_used: if c.count > 0 { c.last_used } else { 0 },
+          policy: c.policy,
+          stored: c.stored_password.is_some(),
+          password: with_passwords.then(|| {
+            Zeroizing::new(key_container.password(
+              &c.domain,
+              &c.identity,
+              c.generation,
+            ))
+          }),
+        })
+        .collect::<Vec<_>>();
+      let count = rows.len();
+      let rendered = inventory::render(&rows, *layout);
+      // Zero the passwords as soon as they are no longer needed.
+      drop(rows);
+      crypt::write_file(output, *force, |writer| {
+        Ok(writer.write_all(rendered.as_bytes())?)
+      })?;
+      drop(rendered);
+      out::info(
+        "Export",
+        &format!("wrote {} credentials to {}", count, output.display()),
+      )?;
+      if cli.format == cli::Format::Json {
+        record::Record::Export {
+          path: output.display().to_string(),
+          credentials: count,
+          passwords: *with_passwords,
+        }
+        .print()?;
+      }
+    }
+    Some(cli::Command::Stored { command }) => {
+      let Some(mut key_container) = ask_skeleton_key(&app_state)? else {
+        return aborted(cli);
+      };
+      let state = &mut key_container.state;
+      match command {
+        cli::StoredCommand::Set { domain, identity } => {
+          let Some(password) = ask_new_secret("password")? else {
+            return aborted(cli);
+          };
+          app_state.update_key_state(state, |state| {
+            state.set_stored_password(domain, identity, password.as_str())
+          })?;
+          report_stored_password(
+            cli,
+            domain,
+            identity,
+            StoredPasswordState::Stored,
+          )?;
+        }
+        cli::StoredCommand::Clear { domain, identity } => {
+          let stored_state = if app_state.update_key_state(state, |state| {
+            state.clear_stored_password(domain, identity)
+          })? {
+            StoredPasswordState::Cleared
+          } else {
+            StoredPasswordState::NotStored
+          };
+          report_stored_password(cli, domain, identity, stored_state)?;
+        }
+        cli::StoredCommand::Report => {
+          let stored = state
+            .secret
+            .credentials
+            .iter()
+            .filter(|c| c.stored_password.is_some())
+            .collect::<Vec<_>>();
+          if stored.is_empty() {
+            out::info("Stored", "no credentials are on stored passwords")?;
+          }
+          for c in stored {
+            report_stored_password(
+              cli,
+              &c.domain,
+              &c.identity,
+              StoredPasswordState::Stored,
+            )?;
+          }
+        }
+      }
+    }
+    Some(cli::Command::Question { command }) => {
+      let Some(mut key_container) = ask_skeleton_key(&app_state)? else {
+        return aborted(cli);
+      };
+      match command {
+        cli::QuestionCommand::Add {
+          domain,
+          identity,
+          question,
+        } => {
+          if !app_state.update_key_state(&mut key_container.state, |state| {
+            state.add_question(domain, identity, question)
+          })? {
+            out::warn("Question", "stored already")?;
+          }
+          report_question(
+            cli,
+            &key_container,
+            domain,
+            identity,
+            question,
+            QuestionState::Stored,
+          )?;
+        }
+        cli::QuestionCommand::Remove {
+          domain,
+          identity,
+          question,
+        } => {
+          match app_state
+            .update_key_state(&mut key_container.state, |state| {
+              state.remove_question(domain, identity, question)
+            })? {
+            Some(stored) => {
+              report_question(
+                cli,
+                &key_container,
+                domain,
+                identity,
+                &stored,
+                QuestionState::Removed,
+              )?;
+            }
+            None => report_question(
+              cli,
+              &key_container,
+              domain,
+              identity,
+              question,
+              QuestionState::NotFound,
+            )?,
+          }
+        }
+        cli::QuestionCommand::List { domain, identity } => {
+          let questions = key_container
+            .state
+            .find(domain, identity)
+            .map_or(Vec::new(), |c| c.questions.clone());
+          for question in questions.iter() {
+            report_question(
+              cli,
+              &key_container,
+              domain,
+              identity,
+              question,
+              QuestionState::Stored,
+            )?;
+          }
+        }
+      }
+    }
+    Some(cli::Command::Note { command }) => {
+      let Some(mut key_container) = ask_skeleton_key(&app_state)? else {
+        return aborted(cli);
+      };
+      let state = &mut key_container.state;
+      match command {
+        cli::NoteCommand::Add {
+          title,
+          domain,
+          identity,
+        } => {
+          if state.find_note(title).is_some() {
+            return Err(notes::Error::Exists(title.clone()).into());
+          }
+          let text = notes::edit("")?;
+          if text.trim().is_empty() {
+            return Err(notes::Error::Empty.into());
+          }
+          let credentials = domain.as_deref().zip(identity.as_deref());
+          if !app_state.update_key_state(state, |state| {
+            state.add_note(title, credentials, &text)
+          })? {
+            return Err(notes::Error::Exists(title.clone()).into());
+          }
+          report_note(cli, find_note(state, title)?, NoteState::Added, false)?;
+        }
+        cli::NoteCommand::View { title } => {
+          report_note(cli, find_note(state, title)?, NoteState::Stored, true)?;
+        }
+        cli::NoteCommand::Edit { title } => {
+          let note = find_note(state, title)?;
+          let text = notes::edit(&note.text)?;
+          if text == note.text.as_ref() {
+            report_note(cli, note, NoteState::Unchanged, false)?;
+          } else if text.trim().is_empty() {
+            return Err(notes::Error::Empty.into());
+          } else {
+            if !app_state.update_key_state(state, |state| {
+              state.set_note_text(title, &text)
+            })? {
+              return Err(notes::Error::NoSuchNote(title.clone()).into());
+            }
+            report_note(
+              cli,
+              find_note(state, title)?,
+              NoteState::Edited,
+              false,
+            )?;
+          }
+        }
+        cli::NoteCommand::Delete { title } => {
+          let Some(note) = app_state
+            .update_key_state(state, |state| state.remove_note(title))?
+          else {
+            return Err(notes::Error::NoSuchNote(title.clone()).into());
+          };
+          report_note(cli, &note, NoteState::Deleted, false)?;
+        }
+        cli::NoteCommand::List { domain, identity } => {
+          let titles = match domain.as_deref().zip(identity.as_deref()) {
+            Some((domain, identity)) => state
+              .notes_of(domain, identity)
+              .map(|n| n.title.to_string())
+              .collect::<Vec<_>>(),
+            None => state
+              .secret
+              .notes
+              .iter()
+              .map(|n| n.title.to_string())
+              .collect(),
+          };
+          for title in titles.iter() {
+            report_note(
+              cli,
+              find_note(state, title)?,
+              NoteState::Stored,
+              false,
+            )?;
+          }
+        }
+      }
+    }
+    Some(cli::Command::Totp { command }) => {
+      let Some(mut key_container) = ask_skeleton_key(&app_state)? else {
+        return aborted(cli);
+      };
+      let (domain, identity, label, state) = match command {
+        cli::TotpCommand::Add { domain, identity } => {
+          let Some(uri) = ask_totp_uri()? else {
+            return aborted(cli);
+          };
+          let (label, _) = totp::Totp::from_uri(&uri)?;
+          if app_state.update_key_state(&mut key_container.state, |state| {
+            state.add_totp_uri(domain, identity, uri.trim())
+          })? {
+            out::info("TOTP", &format!("imported {}", label))?;
+          } else {
+            out::warn("TOTP", &format!("{} was imported already", label))?;
+          }
+          (domain, identity, label, record::TotpState::Imported)
+        }
+        cli::TotpCommand::Remove {
+          domain,
+          identity,
+          index,
+        } => {
+          let imported = key_container.imported_totps(domain, identity);
+          let index = match (index, imported.len()) {
+            (Some(index), _) => *index,
+            (None, 0) => return Err(totp::Error::NoSuchSecret.into()),
+            (None, 1) => 0,
+            (None, _) => {
+              match answer::<ListItem>(prompt_one(
+                Question::select("secret")
+                  .message("Secret to remove")
+                  .choices(imported.iter().map(|(label, _)| label.clone()))
+                  .on_esc(OnEsc::Terminate),
+              ))? {
+                Answer::Value(ListItem { index, .. }) => index,
+                _ => return aborted(cli),
+              }
+            }
+          };
+          let Some(uri) = app_state
+            .update_key_state(&mut key_container.state, |state| {
+              state.remove_totp_uri(domain, identity, index)
+            })?
+          else {
+            return Err(totp::Error::NoSuchSecret.into());
+          };
+          let (label, _) = totp::Totp::from_uri(&uri)?;
+          out::info("TOTP", &format!("removed {}", label))?;
+          (domain, identity, label, record::TotpState::Removed)
+        }
+      };
+      if cli.format == cli::Format::Json {
+        record::Record::Totp {
+          domain: domain.clone(),
+          identity: identity.clone(),
+          label,
+          state,
+        }
+        .print()?;
+      }
+    }
+    Some(cli::Command::SshKey {
+      domain,
+      identity,
+      purpose,
+      output,
+      comment,
+      passphrase,
+      force,
+    }) => {
+      let Some(key_container) = ask_skeleton_key(&app_state)? else {
+        return aborted(cli);
+      };
+      let comment = match comment {
+        Some(comment) => comment.clone(),
+        None => format!("{}@{}", identity, domain),
+      };
+      let key = key_container.ssh_key(domain, identity, purpose, &comment)?;
+      let passphrase = if *passphrase {
+        let Some(passphrase) = ask_new_secret("passphrase")? else {
+          return aborted(cli);
+        };
+        Some(passphrase)
+      } else {
+        None
+      };
+      ssh::write(&key, output, passphrase.as_ref(), *force)?;
+      let public_key = key.public_key().to_openssh()?;
+      out::info("SSH key", &format!("wrote {}", output.display()))?;
+      out::info("SSH key", &public_key)?;
+      if cli.format == cli::Format::Json {
+        record::Record::SshKey {
+          domain: domain.clone(),
+          identity: identity.clone(),
+          purpose: purpose.clone(),
+          public_key,
+          path: output.display().to_string(),
+        }
+        .print()?;
+      }
+    }
+    Some(cli::Command::Import {
+      source,
+      path,
+      store_differing,
+      dry_run,
+    }) => {
+      let entries = import::read(*source, path)?;
+      let Some(mut key_container) = ask_skeleton_key(&app_state)? else {
+        return aborted(cli);
+      };
+      let mut differing = 0;
+      // The pairs and the stored passwords to save to the state as saved on
+      // the disk.
+      let (mut imported, mut stored) = (Vec::new(), Vec::new());
+      for entry in entries.iter() {
+        let (Some(domain), Some(identity)) =
+          (entry.domain.as_deref(), entry.identity.as_deref())
+        else {
+          report_import(cli, entry, ImportState::Skipped, None)?;
+          continue;
+        };
+        let state = &mut key_container.state;
+        let import_state = if state.find(domain, identity).is_some() {
+          ImportState::Known
+        } else {
+          ImportState::Added
+        };
+        if !*dry_run {
+          state.import(domain, identity, entry.notes.as_deref());
+          imported.push((domain, identity, entry.notes.as_deref()));
+        }
+        let generation = key_container.state.generation(domain, identity);
+        let check = match entry.password.as_deref().map(String::as_str) {
+          None => PasswordCheck::Missing,
+          Some(password)
+            if password
+              == key_container.password(domain, identity, generation) =>
+          {
+            PasswordCheck::Matches
+          }
+          Some(password) if *store_differing => {
+            if !*dry_run {
+              key_container
+                .state
+                .set_stored_password(domain, identity, password);
+              stored.push((domain, identity, password));
+            }
+            PasswordCheck::Stored
+          }
+          Some(_) => {
+            differing += 1;
+            PasswordCheck::Differs
+          }
+        };
+        report_import(cli, entry, import_state, Some(check))?;
+      }
+      if !*dry_run {
+        app_state.update_key_state(&mut key_container.state, |state| {
+          for (domain, identity, notes) in imported {
+            state.import(domain, identity, notes);
+          }
+          for (domain, identity, password) in stored {
+            state.set_stored_password(domain, identity, password);
+          }
+        })?;
+      }
+      if differing > 0 {
+        out::warn(
+          "Import",
+          &format!(
+            "{} of the passwords differ from the derived ones; change them or \
+             store them with --store-differing",
+            differing
+          ),
+        )?;
+      }
+      if *source != import::Format::Pass {
+        out::warn("Import", "remember to shred the plain text export")?;
+      }
+    }
+    Some(cli::Command::Breaches { list }) => {
+      let list = breach::PwnedList::open(list)?;
+      let Some(key_container) = ask_skeleton_key(&app_state)? else {
+        return aborted(cli);
+      };
+      let credentials = &key_container.state.secret.credentials;
+      let mut breached = 0;
+      for c in credentials {
+        let password = Zeroizing::new(key_container.password(
+          &c.domain,
+          &c.identity,
+          c.generation,
+        ));
+        let count = list.count(&password)?;
+        if count > 0 {
+          breached += 1;
+        }
+        report_breach(cli, &c.domain, &c.identity, count)?;
+      }
+      let summary = format!(
+        "{} of {} passwords found in breaches",
+        breached,
+        credentials.len()
+      );
+      if breached > 0 {
+        out::warn("Breaches", &summary)?;
+      } else {
+        out::info("Breaches", &summary)?;
+      }
+    }
+    Some(cli::Command::Encrypt {
+      input,
+      output,
+      force,
+    }) => {
+      let output = match output {
+        Some(output) => output.clone(),
+        None => crypt::output_path(input, true)?,
+      };
+      let Some(key_container) = ask_skeleton_key(&app_state)? else {
+        return aborted(cli);
+      };
+      crypt::encrypt_file(
+        &key_container.key_source(),
+        &key_container.state.public.fingerprint,
+        input,
+        &output,
+        *force,
+      )?;
+      report_file(cli, FileOperation::Encrypt, input, &output)?;
+    }
+    Some(cli::Command::Decrypt {
+      input,
+      output,
+      force,
+    }) => {
+      let output = match output {
+        Some(output) => output.clone(),
+        None => crypt::output_path(input, false)?,
+      };
+      let Some(key_container) = ask_skeleton_key(&app_state)? else {
+        return aborted(cli);
+      };
+      crypt::decrypt_file(
+        &key_container.key_source(),
+        &key_container.state.public.fingerprint,
+        input,
+        &output,
+        *force,
+      )?;
+      report_file(cli, FileOperation::Decrypt, input, &output)?;
+    }
+    Some(cli::Command::Lock) => {
+      let socket_path = agent_socket_path(cli)?;
+      match agent::Client::connect(&socket_path) {
+        Ok(mut client) => {
+          client.request(&agent::Request::Lock)?;
+          report_status(
+            cli,
+            Service::Agent,
+            ServiceState::Locked,
+            &socket_path,
+          )?;
+        }
+        Err(agent::Error::NotRunning) => report_status(
+          cli,
+          Service::Agent,
+          ServiceState::NotRunning,
+          &socket_path,
+        )?,
+        Err(err) => return Err(err.into()),
+      }
+    }
+    Some(cli::Command::Get {
+      domain,
+      identity,
+      generation,
+      reveal,
+    }) => {
+      // Use the agent if there is one running and fall back to asking the key
+      // otherwise.
+      let (password, generation, fingerprint, policy) =
+        match agent::Client::connect(&agent_socket_path(cli)?) {
+          Ok(mut client) => {
+            let (password, generation, policy) =
+              client.password(domain, identity, *generation)?;
+            (password, generation, client.fingerprint()?, policy)
+          }
+          Err(agent::Error::NotRunning) => {
+            let Some(mut key_container) = ask_skeleton_key(&app_state)? else {
+              return aborted(cli);
+            };
+            app_state.update_key_state(&mut key_container.state, |state| {
+              state.touch(domain, identity)
+            })?;
+            let generation = generation.unwrap_or_else(|| {
+              key_container.state.generation(domain, identity)
+            });
+            (
+              key_container.password(domain, identity, generation),
+              generation,
+              key_container.state.public.fingerprint.to_string(),
+              key_container.policy(domain, identity),
+            )
+          }
+          Err(err) => return Err(err.into()),
+        };
+      match cli.format {
+        cli::Format::Text if *reveal => out::show_key(generation, &password)?,
+        cli::Format::Text => copy_to_clipboard(cli, &password)?,
+        cli::Format::Json => {
+          if !*reveal {
+            copy_to_clipboard(cli, &password)?;
+          }
+          record::Record::Credentials {
+            domain: domain.clone(),
+            identity: identity.clone(),
+            generation,
+            fingerprint,
+            policy,
+            password: reveal.then_some(password),
+          }
+          .print()?;
+        }
+      }
+    }
+    Some(cli::Command::NativeHost { .. }) => unreachable!(),
+  }
+  Ok(())
+}
+
+/// Finishes a command the user backed out of.  This is an error only for the
+/// scripts reading the machine-readable output.
+fn aborted(cli: &cli::Cli) -> anyhow::Result<()> {
+  match cli.format {
+    cli::Format::Text => Ok(()),
+    cli::Format::Json => Err(record::Error::Aborted.into()),
+  }
+}
+
+/// Reports the state of a service to the user and, when asked for, as a
+/// status record.
+fn report_status(
+  cli: &cli::Cli,
+  service: Service,
+  state: ServiceState,
+  socket_path: &Path,
+) -> io::Result<()> {
+  let heading = match service {
+    Service::Agent => "Agent",
+    Service::Api => "API",
+  };
+  match state {
+    ServiceState::Listening => {
+      out::info(heading, &format!("listening on {}", socket_path.display()))?
+    }
+    ServiceState::Locked => out::info(heading, "locked")?,
+    ServiceState::Stopped => out::info(heading, "stopped")?,
+    ServiceState::NotRunning => out::warn(heading, "not running")?,
+  }
+  if cli.format == cli::Format::Json {
+    record::Record::Status {
+      service,
+      state,
+      socket: socket_path.display().to_string(),
+    }
+    .print()?;
+  }
+  Ok(())
+}
+
+/// Tells whether the credentials are on a stored password and, when asked
+/// for, writes it as a stored password record.
+fn report_stored_password(
+  cli: &cli::Cli,
+  domain: &str,
+  identity: &str,
+  state: StoredPasswordState,
+) -> io::Result<()> {
+  let credentials = format!("{} @ {}", identity, domain);
+  match state {
+    StoredPasswordState::Stored => out::info("Stored", &credentials)?,
+    StoredPasswordState::Cleared => out::info(
+      "Cleared",
+      &format!("{} is on the derived password", credentials),
+    )?,
+    StoredPasswordState::NotStored => out::warn(
+      "Not stored",
+      &format!("{} has no stored password", credentials),
+    )?,
+  }
+  if cli.format == cli::Format::Json {
+    record::Record::StoredPassword {
+      domain: domain.to_owned(),
+      identity: identity.to_owned(),
+      state,
+    }
+    .print()?;
+  }
+  Ok(())
+}
+
+/// Shows the security question with its answer or reports what became of it
+/// and, when asked for, writes it as a question record.
+fn report_question(
+  cli: &cli::Cli,
+  key_container: &KeyContainer,
+  domain: &str,
+  identity: &str,
+  question: &str,
+  state: QuestionState,
+) -> io::Result<()> {
+  let answer = match state {
+    QuestionState::Stored => {
+      let answer = key_container.security_answer(domain, identity, question);
+      out::info(question, &answer)?;
+      Some(answer)
+    }
+    QuestionState::Removed => {
+      out::info("Question", &format!("removed \"{}\"", question))?;
+      None
+    }
+    QuestionState::NotFound => {
+      out::warn("Question", "no such question")?;
+      None
+    }
+  };
+  if cli.format == cli::Format::Json {
+    record::Record::Question {
+      domain: domain.to_owned(),
+      identity: identity.to_owned(),
+      question: question.to_owned(),
+      answer,
+      state,
+    }
+    .print()?;
   }
   Ok(())
 }
 
+/// Returns the note with the given title or fails if there is none.
+fn find_note<'a>(
+  state: &'a state::KeyState,
+  title: &str,
+) -> Result<&'a state::SecretNote, notes::Error> {
+  state
+    .find_note(title)
+    .ok_or_else(|| notes::Error::NoSuchNote(title.to_owned()))
+}
+
+/// Tells what became of the note and, when asked for, writes it as a note
+/// record.  The text of the note is shown only with `show_text`.
+fn report_note(
+  cli: &cli::Cli,
+  note: &state::SecretNote,
+  note_state: NoteState,
+  show_text: bool,
+) -> io::Result<()> {
+  let title = note.title.as_ref();
+  let credentials = note
+    .domain
+    .as_deref()
+    .zip(note.identity.as_deref())
+    .map(|(domain, identity)| format!(" ({} @ {})", identity, domain))
+    .unwrap_or_default();
+  let outcome = match note_state {
+    NoteState::Stored => "",
+    NoteState::Added => "added ",
+    NoteState::Edited => "edited ",
+    NoteState::Unchanged => "unchanged ",
+    NoteState::Deleted => "deleted ",
+  };
+  out::info("Note", &format!("{}{}{}", outcome, title, credentials))?;
+  if show_text {
+    let mut stdout = out::stdout();
+    stdout.write_all(note.text.as_bytes())?;
+    if !note.text.ends_with('\n') {
+      stdout.write_all(b"\n")?;
+    }
+  }
+  if cli.format == cli::Format::Json {
+    record::Record::Note {
+      title: title.to_owned(),
+      domain: note.domain.as_deref().map(str::to_owned),
+      identity: note.identity.as_deref().map(str::to_owned),
+      text: show_text.then(|| note.text.to_string()),
+      state: note_state,
+    }
+    .print()?;
+  }
+  Ok(())
+}
+
+/// Tells what became of the imported entry and, when asked for, writes it as
+/// an import record.
+fn report_import(
+  cli: &cli::Cli,
+  entry: &import::Entry,
+  state: ImportState,
+  check: Option<PasswordCheck>,
+) -> io::Result<()> {
+  match (entry.domain.as_deref(), entry.identity.as_deref(), check) {
+    (Some(domain), Some(identity), Some(check)) => {
+      let title = match state {
+        ImportState::Added => "Added",
+        _ => "Known",
+      };
+      let password = match check {
+        PasswordCheck::Matches => "password matches",
+        PasswordCheck::Differs => "password differs",
+        PasswordCheck::Stored => "password differs and is stored",
+        PasswordCheck::Missing => "no password",
+      };
+      out::info(title, &format!("{} @ {} ({})", identity, domain, password))?;
+    }
+    _ => out::warn(
+      "Skipped",
+      &format!("{}: no domain or identity", entry.title),
+    )?,
+  }
+  if cli.format == cli::Format::Json {
+    record::Record::Import {
+      title: entry.title.clone(),
+      domain: entry.domain.clone(),
+      identity: entry.identity.clone(),
+      state,
+      password: check,
+    }
+    .print()?;
+  }
+  Ok(())
+}
+
+/// Tells how many times the password of the credentials has been seen in
+/// breaches and, when asked for, writes it as a breach record.
+fn report_breach(
+  cli: &cli::Cli,
+  domain: &str,
+  identity: &str,
+  count: u64,
+) -> io::Result<()> {
+  if count > 0 {
+    out::warn(
+      "Breached",
+      &format!("{} @ {} (seen {} times)", identity, domain, count),
+    )?;
+  } else {
+    out::info("Not breached", &format!("{} @ {}", identity, domain))?;
+  }
+  if cli.format == cli::Format::Json {
+    record::Record::Breach {
+      domain: domain.to_owned(),
+      identity: identity.to_owned(),
+      count,
+    }
+    .print()?;
+  }
+  Ok(())
+}
+
+/// Tells where the encrypted or decrypted file went and, when asked for,
+/// writes it as a file record.
+fn report_file(
+  cli: &cli::Cli,
+  operation: FileOperation,
+  input: &Path,
+  output: &Path,
+) -> io::Result<()> {
+  let title = match operation {
+    FileOperation::Encrypt => "Encrypted",
+    FileOperation::Decrypt => "Decrypted",
+  };
+  out::info(title, &format!("wrote {}", output.display()))?;
+  if cli.format == cli::Format::Json {
+    record::Record::File {
+      operation,
+      input: input.display().to_string(),
+      output: output.display().to_string(),
+    }
+    .print()?;
+  }
+  Ok(())
+}
+
+fn agent_socket_path(cli: &cli::Cli) -> Result<PathBuf, state::Error> {
+  match &cli.agent_socket {
+    Some(path) => Ok(path.clone()),
+    None => agent::default_socket_path(),
+  }
+}
+
+/// Copies the password to the clipboard falling back to revealing it if the
+/// clipboard is not available.
+fn copy_to_clipboard(cli: &cli::Cli, password: &str) -> io::Result<()> {
+  match clipboard::copy(password, &cli.clipboard_options()) {
+    Ok(()) => Ok(()),
+    // A backend that cannot be run or whose pipe breaks is no different
+    // from one that fails otherwise.
+    Err(err) => {
+      let reason = match &err {
+        clipboard::Error::Io(io_err) => format!("{} ({})", err, io_err),
+        _ => err.to_string(),
+      };
+      out::warn("Clipboard", &format!("{}, revealing instead", reason))?;
+      out::show_key(0, password)
+    }
+  }
+}
+
+/// Asks the user whether to allow an API call.  Returns `None` if the user
+/// wants to stop serving.
+fn confirm_api_call(what: &str) -> io::Result<Option<bool>> {
+  confirm(&format!("Allow a client {}", what))
+}
+
+/// Asks a yes-or-no question defaulting to no.  Returns `None` if the user
+/// backs out.
+fn confirm(question: &str) -> io::Result<Option<bool>> {
+  Ok(
+    match answer::<bool>(prompt_one(
+      Question::confirm("confirm")
+        .message(question)
+        .default(false)
+        .on_esc(OnEsc::Terminate),
+    ))? {
+      Answer::Value(allow) => Some(allow),
+      Answer::Interrupted => None,
+      _ => Some(false),
+    },
+  )
+}
+
+/// Asks for the `otpauth://` URI of a secret issued by a service.  Returns
+/// `None` if the user backs out.
+fn ask_totp_uri() -> io::Result<Option<String>> {
+  Ok(
+    match answer::<String>(prompt_one(
+      Question::password("uri")
+        .message("otpauth URI")
+        .mask('*')
+        .validate(|uri, _| match totp::Totp::from_uri(uri) {
+          Ok(_) => Ok(()),
+          Err(err) => Err(format!("Invalid URI: {}", err)),
+        })
+        .on_esc(OnEsc::Terminate),
+    ))? {
+      Answer::Value(uri) => Some(uri),
+      _ => None,
+    },
+  )
+}
+
+/// Asks for a new secret, such as a passphrase, twice.  The `noun` names the
+/// secret in the prompts.  Returns `None` if the user backs out.
+fn ask_new_secret(noun: &str) -> io::Result<Option<Passphrase>> {
+  let passphrase = match answer::<String>(prompt_one(
+    Question::password("passphrase")
+      .message(format!("New {}", noun))
+      .mask('*')
+      .validate(|passphrase, _| {
+        if passphrase.is_empty() {
+          Err(format!("{} cannot be empty", upcase_first(noun)))
+        } else {
+          Ok(())
+        }
+      })
+      .on_esc(OnEsc::Terminate),
+  ))? {
+    Answer::Value(passphrase) => passphrase,
+    _ => return Ok(None),
+  };
+  Ok(
+    match answer::<String>(prompt_one(
+      Question::password("confirmation")
+        .message(format!("Re-enter {}", noun))
+        .mask('*')
+        .validate(|confirmation, _| {
+          if confirmation == passphrase {
+            Ok(())
+          } else {
+            Err(format!("The confirmation does not match the {}", noun))
+          }
+        })
+        .on_esc(OnEsc::Terminate),
+    ))? {
+      Answer::Value(_) => Some(passphrase.into()),
+      _ => None,
+    },
+  )
+}
+
+/// Shows the current code of the one-time password generator.
+fn show_totp_code(heading: &str, totp: &totp::Totp) -> io::Result<()> {
+  let now = state::unix_time();
+  out::info(
+    heading,
+    &format!("{} (valid for {} s)", totp.code(now), totp.remaining(now)),
+  )
+}
+
+/// Returns the fingerprint identifying the skeleton key.
+fn fingerprint(skeleton_key: &Passphrase) -> String {
+  format_key(&KeySource::new(skeleton_key.as_str()).fingerprint(), 8)
+}
+
 fn ask_skeleton_key(
   state: &state::AppState,
 ) -> Result<Option<KeyContainer>, state::Error> {
@@ -69,8 +1306,7 @@ fn ask_skeleton_key(
 
     let skeleton_key: Passphrase = skeleton_key_str.into();
 
-    let key_source = KeySource::new(skeleton_key.as_str());
-    let fingerprint = format_key(&key_source.fingerprint(), 8);
+    let fingerprint = fingerprint(&skeleton_key);
     if state.is_known(&fingerprint)? {
       let key_state = state.load_key_state(&skeleton_key, &fingerprint)?;
       out::show_known_key_message(&fingerprint)?;
@@ -127,64 +1363,40 @@ fn ask_skeleton_key(
   }
 }
 
-use requestty::question::completions;
+use requestty::question::{completions, Completions};
 
 fn domain_identity_loop(
+  cli: &cli::Cli,
   app_state: &state::AppState,
   key_container: &mut KeyContainer,
 ) -> Result<bool, state::Error> {
   loop {
     // This limits the scope of borrowing the credentials from the key state.
 
-    let domain = match answer::<String>(prompt_one(
-      Question::input("domain")
-        .message("Domain")
+    let search = match answer::<String>(prompt_one(
+      Question::input("credentials")
+        .message("Credentials")
         .validate_on_key(|input, _| !input.is_empty() && input.trim() == input)
         .validate(|input, _| {
           if input.trim() != input {
-            Err("Domain cannot have leading or trailing whitespace".to_owned())
+            Err("Input cannot have leading or trailing whitespace".to_owned())
           } else if input.is_empty() {
-            Err("Domain cannot be empty (press Esc to close key)".to_owned())
+            Err("Input cannot be empty (press Esc to close key)".to_owned())
           } else {
             Ok(())
           }
         })
         .auto_complete(|partial, _| {
-          struct Candidate {
-            domain: Box<str>,
-            total_count: u16,
-          }
-          let mut candidates = Vec::<Candidate>::new();
-          for credentials in key_container.state.secret.credentials.iter() {
-            if credentials.domain.starts_with(&partial) {
-              if let Some(candidate) = candidates
-                .iter_mut()
-                .find(|candidate| candidate.domain == credentials.domain)
-              {
-                candidate.total_count += credentials.count;
-              } else {
-                candidates.push(Candidate {
-                  domain: credentials.domain.clone(),
-                  total_count: credentials.count,
-                });
-              }
-            }
-          }
-          if !candidates.is_empty() {
-            candidates.sort_by(|l, r| {
-              // primary factor = descending in total count
-              // secondary factor = ascending in domain
-              match r.total_count.cmp(&l.total_count) {
-                Ordering::Equal => l.domain.as_ref().cmp(&r.domain),
-                ordering => ordering,
-              }
-            });
-            return candidates
-              .into_iter()
-              .map(|candidate| candidate.domain.to_string())
-              .collect();
+          let found = fuzzy::search(
+            &partial,
+            key_container.state.secret.credentials.iter(),
+            state::unix_time(),
+          );
+          if found.is_empty() {
+            completions![partial]
+          } else {
+            found.into_iter().map(|c| c.label()).collect()
           }
-          completions![partial]
         })
         .on_esc(OnEsc::Terminate),
     ))? {
@@ -193,105 +1405,202 @@ fn domain_identity_loop(
       _ => return Ok(false),
     };
 
-    let identity = match answer::<String>(prompt_one(
-      Question::input("identity")
-        .message("Identity")
-        .validate_on_key(|input, _| !input.is_empty() && input.trim() == input)
-        .validate(|input, _| {
-          if input.trim() != input {
-            Err(
-              "Identity cannot have leading or trailing whitespace".to_owned(),
-            )
-          } else if input.is_empty() {
-            Err("Identity cannot be empty".to_owned())
-          } else {
-            Ok(())
-          }
-        })
-        .auto_complete(|partial, _| {
-          struct Candidate {
-            identity: Box<str>,
-            count: u16,
-          }
-          let mut candidates = key_container
-            .state
-            .secret
-            .credentials
-            .iter()
-            .filter_map(|credentials| {
-              if credentials.domain.as_ref() == domain
-                && credentials.identity.starts_with(&partial)
-              {
-                Some(Candidate {
-                  identity: credentials.identity.clone(),
-                  count: credentials.count,
-                })
-              } else {
-                None
-              }
-            })
-            .collect::<Vec<_>>();
-          if !candidates.is_empty() {
-            candidates.sort_by(|l, r| match r.count.cmp(&l.count) {
-              // primary ordering = descending in count
-              // secondary ordering = ascending in identity
-              Ordering::Equal => l.identity.as_ref().cmp(&r.identity),
-              ordering => ordering,
-            });
-            return candidates
+    // Either the input picks a pair in the "identity @ domain" form or, failing
+    // that, it is taken as the domain and we go on to ask for the identity.
+    let (domain, identity) = if let Some((domain, identity)) =
+      state::Credentials::parse_label(&search)
+    {
+      (domain.to_owned(), identity.to_owned())
+    } else {
+      let domain = search;
+      let identity = match answer::<String>(prompt_one(
+        Question::input("identity")
+          .message("Identity")
+          .validate_on_key(|input, _| {
+            !input.is_empty() && input.trim() == input
+          })
+          .validate(|input, _| {
+            if input.trim() != input {
+              Err(
+                "Identity cannot have leading or trailing whitespace"
+                  .to_owned(),
+              )
+            } else if input.is_empty() {
+              Err("Identity cannot be empty".to_owned())
+            } else {
+              Ok(())
+            }
+          })
+          .auto_complete(|partial, _| {
+            let candidates = key_container
+              .state
+              .secret
+              .ranked(state::unix_time())
               .into_iter()
-              .map(|candidate| candidate.identity.to_string())
-              .collect();
-          }
-          completions![partial]
-        })
-        .on_esc(OnEsc::Terminate),
-    ))? {
-      Answer::Value(v) => v,
-      Answer::Aborted => continue,
-      _ => return Ok(false),
+              .filter(|c| {
+                c.domain.as_ref() == domain && c.identity.starts_with(&partial)
+              })
+              .map(|c| c.identity.to_string())
+              .collect::<Completions<_>>();
+            if candidates.is_empty() {
+              completions![partial]
+            } else {
+              candidates
+            }
+          })
+          .on_esc(OnEsc::Terminate),
+      ))? {
+        Answer::Value(v) => v,
+        Answer::Aborted => continue,
+        _ => return Ok(false),
+      };
+      (domain, identity)
     };
 
     assert!(!identity.is_empty());
 
     enum Action {
       CopyToClipboard,
+      EmitToCommand,
       Reveal,
+      ShowQr,
+      ShowTotpCode,
+      EnrollTotp,
+      ShowAnswers,
+      ShowNotes,
+    }
+
+    let mut choices = vec![
+      ('c', "Copy to clipboard"),
+      ('r', "Reveal"),
+      ('q', "Show QR"),
+      ('t', "Show TOTP code"),
+      ('o', "Enroll TOTP"),
+      ('a', "Show security answers"),
+      ('n', "Show notes"),
+    ];
+    if cli.emit_command.is_some() {
+      choices.push(('e', "Emit to command"));
     }
 
     let action = match answer::<ExpandItem>(prompt_one(
       Question::expand("action")
         .message("Action")
-        .choices([('c', "Copy to clipboard"), ('r', "Reveal")])
+        .choices(choices)
         .default('c')
         .on_esc(OnEsc::Terminate),
     ))? {
       Answer::Value(ExpandItem { key, .. }) => match key {
         'c' => Action::CopyToClipboard,
+        'e' => Action::EmitToCommand,
         'r' => Action::Reveal,
+        'q' => Action::ShowQr,
+        't' => Action::ShowTotpCode,
+        'o' => Action::EnrollTotp,
+        'a' => Action::ShowAnswers,
+        'n' => Action::ShowNotes,
         _ => unreachable!(),
       },
       Answer::Aborted => continue,
       _ => return Ok(false),
     };
 
-    key_container.state.touch(&domain, &identity);
-    app_state.save_key_state(&key_container.state)?;
+    // The session may have been open for long, so the use is saved on top
+    // of what other invocations have saved meanwhile.
+    app_state.update_key_state(&mut key_container.state, |state| {
+      state.touch(&domain, &identity)
+    })?;
+    let generation = key_container.state.generation(&domain, &identity);
 
-    // XXX(soija) TOOD: Get rid of this legacy key source.
-    let key_source = key_container.key_source();
     match action {
       Action::CopyToClipboard => {
-        if let Some(key) = key_source.keys(&domain, &identity).next() {
-          clipboard::copy(&format_key(&key, 4));
-        } else {
+        // The service is likely to ask for the second factor next, so the
+        // codes are shown before the password is copied and stay in view
+        // while it is in the clipboard.
+        for (label, totp) in key_container.imported_totps(&domain, &identity) {
+          show_totp_code(&label, &totp)?;
+        }
+        copy_to_clipboard(
+          cli,
+          &key_container.password(&domain, &identity, generation),
+        )?;
+      }
+      Action::EmitToCommand => {
+        let Some(command_line) = cli.emit_command.as_deref() else {
           unreachable!()
+        };
+        out::countdown(
+          "Emit",
+          "running command",
+          Duration::from_secs(cli.emit_delay),
+          || true,
+        )?;
+        let password = key_container.password(&domain, &identity, generation);
+        match emit::emit(&password, command_line) {
+          Ok(()) => out::info("Emit", "done")?,
+          Err(emit::Error::Io(err)) => {
+            out::warn("Emit", &format!("cannot run the command ({})", err))?
+          }
+          Err(err) => out::warn("Emit", &err.to_string())?,
+        }
+      }
+      Action::ShowTotpCode => {
+        show_totp_code("TOTP", &key_container.totp(&domain, &identity))?;
+        for (label, totp) in key_container.imported_totps(&domain, &identity) {
+          show_totp_code(&label, &totp)?;
         }
       }
+      Action::EnrollTotp => {
+        let totp = key_container.totp(&domain, &identity);
+        let uri = totp.uri(&domain, &identity);
+        out::info("TOTP secret", &totp.secret_base32())?;
+        out::info("TOTP URI", &uri)?;
+        out::show_qr(&uri)?;
+      }
+      Action::ShowAnswers => {
+        let questions = key_container
+          .state
+          .find(&domain, &identity)
+          .map_or(Vec::new(), |c| c.questions.clone());
+        if questions.is_empty() {
+          out::warn("Questions", "none stored (see `skele question add`)")?;
+        }
+        for question in questions.iter() {
+          out::info(
+            question,
+            &key_container.security_answer(&domain, &identity, question),
+          )?;
+        }
+      }
+      Action::ShowNotes => {
+        let mut notes =
+          key_container.state.notes_of(&domain, &identity).peekable();
+        if notes.peek().is_none() {
+          out::warn("Notes", "none stored (see `skele note add`)")?;
+        }
+        for note in notes {
+          out::info("Note", &note.title)?;
+          out::stdout().write_all(note.text.as_bytes())?;
+        }
+      }
+      Action::ShowQr => {
+        out::show_qr(&key_container.password(&domain, &identity, generation))?;
+      }
       Action::Reveal => {
-        for (ix, key) in key_source.keys(&domain, &identity).take(5).enumerate()
+        // A stored password is the same for every generation.
+        if let Some(password) =
+          key_container.state.stored_password(&domain, &identity)
         {
-          out::show_key(ix, &format_key(&key, 4))?;
+          out::info("Stored", password)?;
+        } else {
+          // Show five generations: the one in use and the four before it or,
+          // early on, the first five.
+          let last = generation.max(4);
+          for generation in last - 4..=last {
+            let password =
+              key_container.password(&domain, &identity, generation);
+            out::show_key(generation, &password)?;
+          }
         }
       }
     }
@@ -304,15 +1613,43 @@ fn domain_identity_loop(
 
 mod out {
 
-  use std::io::{self, stdout};
+  use std::{
+    io::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+  };
 
   use crossterm::{
+    cursor::{MoveToColumn, MoveToPreviousLine},
+    event::{self, Event, KeyEventKind},
     style::{
       Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor,
     },
+    terminal::{self, Clear, ClearType},
     ExecutableCommand,
   };
 
+  static TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+  /// Sends the output meant for humans, prompts included, to the standard
+  /// error so that the standard output is left for machine-readable output.
+  pub fn use_stderr() {
+    TO_STDERR.store(true, Ordering::Relaxed);
+  }
+
+  pub fn is_stderr() -> bool {
+    TO_STDERR.load(Ordering::Relaxed)
+  }
+
+  /// Returns the stream the output meant for humans goes to.
+  pub fn stdout() -> Box<dyn Write> {
+    if is_stderr() {
+      Box::new(io::stderr())
+    } else {
+      Box::new(io::stdout())
+    }
+  }
+
   pub fn blurp(
     color: Color,
     symbol: char,
@@ -339,6 +1676,11 @@ mod out {
     blurp(Color::Yellow, '|', heading, message)
   }
 
+  /// Reports the error a command failed with.
+  pub fn error(message: &str) -> io::Result<()> {
+    blurp(Color::Red, '|', "Error", message)
+  }
+
   pub fn show_new_key_warning(fingerprint: &str) -> io::Result<()> {
     stdout()
       .execute(SetForegroundColor(Color::Yellow))?
@@ -386,6 +1728,96 @@ mod out {
     Ok(())
   }
 
+  /// Shows a countdown until `timeout` expires or the user presses a key.
+  /// Stops early if `proceed` returns `false`.  Returns the last value of
+  /// `proceed`.
+  pub fn countdown<F>(
+    heading: &str,
+    what: &str,
+    timeout: Duration,
+    mut proceed: F,
+  ) -> io::Result<bool>
+  where
+    F: FnMut() -> bool,
+  {
+    let deadline = Instant::now() + timeout;
+    terminal::enable_raw_mode()?;
+    let result = (|| loop {
+      if !proceed() {
+        return Ok(false);
+      }
+      let Some(remaining) = deadline.checked_duration_since(Instant::now())
+      else {
+        return Ok(true);
+      };
+      stdout()
+        .execute(MoveToColumn(0))?
+        .execute(Clear(ClearType::CurrentLine))?
+        .execute(SetForegroundColor(Color::Green))?
+        .execute(Print('|'))?
+        .execute(ResetColor)?
+        .execute(Print(" "))?
+        .execute(SetAttribute(Attribute::Bold))?
+        .execute(Print(format!("{}:", heading)))?
+        .execute(SetAttribute(Attribute::Reset))?
+        .execute(Print(format!(
+          " {} in {} s (press any key to do it now)",
+          what,
+          remaining.as_secs_f32().ceil()
+        )))?;
+      if event::poll(remaining.min(Duration::from_millis(200)))? {
+        if let Event::Key(key) = event::read()? {
+          if key.kind == KeyEventKind::Press {
+            return Ok(proceed());
+          }
+        }
+      }
+    })();
+    stdout()
+      .execute(MoveToColumn(0))?
+      .execute(Clear(ClearType::CurrentLine))?;
+    terminal::disable_raw_mode()?;
+    result
+  }
+
+  /// Shows the text as a QR code drawn with half blocks until the user
+  /// presses a key and then clears it from the screen.
+  pub fn show_qr(text: &str) -> io::Result<()> {
+    use qrcode::{render::unicode::Dense1x2, QrCode};
+
+    let Ok(code) = QrCode::new(text.as_bytes()) else {
+      return warn("QR", "the text does not fit into a QR code");
+    };
+    // Inverted so that the code reads right on a terminal with a dark
+    // background.
+    let image = code
+      .render::<Dense1x2>()
+      .dark_color(Dense1x2::Light)
+      .light_color(Dense1x2::Dark)
+      .quiet_zone(true)
+      .build();
+    let mut lines = 0;
+    for line in image.lines() {
+      stdout().execute(Print(format!("{}\n", line)))?;
+      lines += 1;
+    }
+    info("QR", "press any key to clear")?;
+    lines += 1;
+    terminal::enable_raw_mode()?;
+    let result = (|| loop {
+      if let Event::Key(key) = event::read()? {
+        if key.kind == KeyEventKind::Press {
+          return Ok(());
+        }
+      }
+    })();
+    terminal::disable_raw_mode()?;
+    stdout()
+      .execute(MoveToPreviousLine(lines))?
+      .execute(Clear(ClearType::FromCursorDown))?;
+    result
+  }
+
   pub fn show_notice() -> io::Result<()> {
     info("Skele", &format!("version {}", env!("CARGO_PKG_VERSION")))
   }
@@ -432,6 +1864,16 @@ mod requestty_utils {
     }
   }
 
+  impl From<requestty::Answer> for Answer<requestty::ListItem> {
+    fn from(answer: requestty::Answer) -> Self {
+      if let Ok(value) = answer.try_into_list_item() {
+        Answer::Value(value)
+      } else {
+        panic!("illegal answer type");
+      }
+    }
+  }
+
   impl<T> TryFrom<requestty::Result<requestty::Answer>> for Answer<T>
   where
     Answer<T>: From<requestty::Answer>,
@@ -452,6 +1894,20 @@ mod requestty_utils {
     }
   }
 
+  /// Asks the question on the stream used for the output meant for humans.
+  pub fn prompt_one<'a>(
+    question: impl Into<requestty::Question<'a>>,
+  ) -> requestty::Result<requestty::Answer> {
+    let mut events = requestty::prompt::events::get_events();
+    if super::out::is_stderr() {
+      let mut backend = requestty::prompt::backend::get_backend(io::stderr());
+      requestty::prompt_one_with(question, &mut backend, &mut events)
+    } else {
+      let mut backend = requestty::prompt::backend::get_backend(io::stdout());
+      requestty::prompt_one_with(question, &mut backend, &mut events)
+    }
+  }
+
   pub fn answer<T>(
     result: requestty::Result<requestty::Answer>,
   ) -> io::Result<Answer<T>>
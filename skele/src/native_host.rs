@@ -0,0 +1,290 @@
+//! # Browser native messaging host
+//!
+//! Lets a browser extension ask for the passwords of the page being visited.
+//! The host speaks the WebExtension native messaging protocol on the standard
+//! input and output: each message is a JSON object prefixed with its length as
+//! a 32-bit unsigned integer in the native byte order.
+//!
+//! The host never sees the skeleton key.  It forwards the requests to a running
+//! agent (see `skele agent`) and refuses to serve anything while the agent is
+//! not running.
+//!
+//! ## Messages
+//!
+//! - `{"type":"identities","url":"https://github.com/login"}` is answered with
+//!   `{"type":"identities","domain":"github.com","identities":["alice"]}`.  The
+//!   domain is the known domain equal to the host of the URL or `null` if there
+//!   is none.  With `--subdomains` the host may also be a subdomain of a known
+//!   domain, in which case the longest such domain is picked.
+//! - `{"type":"password","url":"…","identity":"alice"}` is answered with
+//!   `{"type":"password","domain":"github.com","identity":"alice",
+//!   "password":"…"}`.  Only the passwords of known credentials are served.
+//!
+//! Any message may be answered with `{"type":"error","message":"…"}`.
+//!
+//! ## Installation
+//!
+//! Browsers start the host by running the program given in the host manifest
+//! with browser specific arguments.  Point the manifest to a wrapper script
+//! such as
+//!
+//! ```sh
+//! #!/bin/sh
+//! exec skele native-host "$@"
+//! ```
+//!
+//! and install the manifest (named `com.mjhanninen.skele.json`) to the native
+//! messaging host directory of the browser:
+//!
+//! ```json
+//! {
+//!   "name": "com.mjhanninen.skele",
+//!   "description": "Skele",
+//!   "path": "/path/to/skele-native-host",
+//!   "type": "stdio",
+//!   "allowed_extensions": ["…"]
+//! }
+//! ```
+
+use std::{
+  io::{self, Read, Write},
+  path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::agent;
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("i/o error")]
+  Io(#[from] io::Error),
+  #[error("message too long")]
+  MessageTooLong,
+}
+
+/// The maximum length of a message in either direction.  Browsers refuse
+/// messages longer than this from the host.
+const MAX_MESSAGE_LEN: usize = 1024 * 1024;
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+  Identities { url: String },
+  Password { url: String, identity: String },
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+  Identities {
+    domain: Option<String>,
+    identities: Vec<String>,
+  },
+  Password {
+    domain: String,
+    identity: String,
+    password: String,
+  },
+  Error {
+    message: String,
+  },
+}
+
+/// Reads a single message.  Returns `None` when the browser has closed the
+/// input.
+fn read_message(reader: &mut impl Read) -> Result<Option<Vec<u8>>, Error> {
+  let mut len = [0u8; 4];
+  match reader.read_exact(&mut len) {
+    Ok(()) => {}
+    Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+    Err(err) => return Err(err.into()),
+  }
+  let len = u32::from_ne_bytes(len) as usize;
+  if len > MAX_MESSAGE_LEN {
+    return Err(Error::MessageTooLong);
+  }
+  let mut message = vec![0u8; len];
+  reader.read_exact(&mut message)?;
+  Ok(Some(message))
+}
+
+fn write_message(
+  writer: &mut impl Write,
+  response: &Response,
+) -> Result<(), Error> {
+  let message = serde_json::to_vec(response).map_err(io::Error::other)?;
+  if message.len() > MAX_MESSAGE_LEN {
+    return Err(Error::MessageTooLong);
+  }
+  writer.write_all(&(message.len() as u32).to_ne_bytes())?;
+  writer.write_all(&message)?;
+  writer.flush()?;
+  Ok(())
+}
+
+/// Extracts the lowercased host name from the URL.
+pub fn url_host(url: &str) -> Option<String> {
+  let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+  let authority = rest.split(['/', '?', '#']).next()?;
+  let host_port = authority.rsplit_once('@').map_or(authority, |(_, h)| h);
+  let host = if host_port.starts_with('[') {
+    // IPv6 literal
+    host_port.split_inclusive(']').next()?
+  } else {
+    host_port.split(':').next()?
+  };
+  let host = host.trim_end_matches('.').to_lowercase();
+  if host.is_empty() {
+    None
+  } else {
+    Some(host)
+  }
+}
+
+/// Resolves the known domain of the host of the URL.  Only the exact host
+/// matches unless `subdomains` is set, in which case the host may also be a
+/// subdomain of a known domain and the most specific match is picked.
+pub fn resolve_domain<'a, I>(
+  url: &str,
+  known: I,
+  subdomains: bool,
+) -> Option<&'a str>
+where
+  I: IntoIterator<Item = &'a str>,
+{
+  let host = url_host(url)?;
+  known
+    .into_iter()
+    .filter(|&domain| {
+      let domain = domain.to_lowercase();
+      host == domain || subdomains && host.ends_with(&format!(".{}", domain))
+    })
+    .max_by_key(|domain| domain.len())
+}
+
+fn respond(
+  socket_path: &Path,
+  subdomains: bool,
+  request: Request,
+) -> Result<Response, agent::Error> {
+  // Connect anew for every request as the agent serves one client at a time.
+  let entries = agent::Client::connect(socket_path)?.list()?;
+  let domains = entries.iter().map(|e| e.domain.as_str());
+  Ok(match request {
+    Request::Identities { url } => {
+      let domain = resolve_domain(&url, domains, subdomains);
+      Response::Identities {
+        identities: entries
+          .iter()
+          .filter(|e| Some(e.domain.as_str()) == domain)
+          .map(|e| e.identity.clone())
+          .collect(),
+        domain: domain.map(str::to_owned),
+      }
+    }
+    Request::Password { url, identity } => {
+      let domain = resolve_domain(&url, domains, subdomains);
+      match domain {
+        Some(domain)
+          if entries
+            .iter()
+            .any(|e| e.domain == domain && e.identity == identity) =>
+        {
+          let (password, ..) = agent::Client::connect(socket_path)?
+            .password(domain, &identity, None)?;
+          Response::Password {
+            domain: domain.to_owned(),
+            identity,
+            password,
+          }
+        }
+        _ => Response::Error {
+          message: "unknown credentials".to_owned(),
+        },
+      }
+    }
+  })
+}
+
+/// Serves the browser until it closes the standard input.  See
+/// [`resolve_domain`] for `subdomains`.
+pub fn serve(socket_path: &Path, subdomains: bool) -> Result<(), Error> {
+  let mut stdin = io::stdin().lock();
+  let mut stdout = io::stdout().lock();
+  while let Some(message) = read_message(&mut stdin)? {
+    let response = match serde_json::from_slice::<Request>(&message) {
+      Ok(request) => match respond(socket_path, subdomains, request) {
+        Ok(response) => response,
+        Err(err) => Response::Error {
+          message: err.to_string(),
+        },
+      },
+      Err(_) => Response::Error {
+        message: "malformed message".to_owned(),
+      },
+    };
+    write_message(&mut stdout, &response)?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn test_url_host() {
+    assert_eq!(url_host("https://github.com/login").unwrap(), "github.com");
+    assert_eq!(
+      url_host("https://alice@Example.COM:8443/x?y#z").unwrap(),
+      "example.com"
+    );
+    assert_eq!(url_host("http://[::1]:8080/").unwrap(), "[::1]");
+    assert_eq!(url_host("example.org").unwrap(), "example.org");
+    assert!(url_host("file:///etc/passwd").is_none());
+  }
+
+  #[test]
+  fn test_resolve_domain_matches_exact_host() {
+    let known = ["github.com", "gist.github.com", "hub.com"];
+    assert_eq!(
+      resolve_domain("https://GitHub.com/login", known, false),
+      Some("github.com")
+    );
+    assert_eq!(
+      resolve_domain("https://gist.github.com/x", known, false),
+      Some("gist.github.com")
+    );
+    assert_eq!(
+      resolve_domain("https://api.github.com/", known, false),
+      None
+    );
+    assert_eq!(
+      resolve_domain("https://evil.gist.github.com/", known, false),
+      None
+    );
+    assert_eq!(
+      resolve_domain("https://github.com.evil/", known, false),
+      None
+    );
+    assert_eq!(resolve_domain("https://example.com/", known, false), None);
+  }
+
+  #[test]
+  fn test_resolve_domain_picks_most_specific() {
+    let known = ["github.com", "gist.github.com", "hub.com"];
+    assert_eq!(
+      resolve_domain("https://gist.github.com/x", known, true),
+      Some("gist.github.com")
+    );
+    assert_eq!(
+      resolve_domain("https://api.github.com/", known, true),
+      Some("github.com")
+    );
+    assert_eq!(resolve_domain("https://evilgithub.com/", known, true), None);
+    assert_eq!(resolve_domain("https://example.com/", known, true), None);
+  }
+}
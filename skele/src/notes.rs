@@ -0,0 +1,129 @@
+//! # Secure notes
+//!
+//! The secret notes are kept in the encrypted key state.  They are edited
+//! with the editor of the user on a temporary file in a private directory on
+//! the per-user runtime directory, which is a tmpfs on systemd, or on
+//! `/dev/shm` so that the plain text never touches the disk.  The files in
+//! the directory, the ones the editor leaves behind included, are overwritten
+//! before they are removed.
+
+use std::{
+  env, fs,
+  io::{self, Read, Write},
+  os::unix::fs::{DirBuilderExt, OpenOptionsExt},
+  path::{Path, PathBuf},
+  process::{self, Command},
+};
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("i/o error")]
+  Io(#[from] io::Error),
+  #[error("no private directory in memory for editing the note")]
+  NoPrivateDir,
+  #[error("the editor failed")]
+  EditorFailed,
+  #[error("note \"{0}\" exists already")]
+  Exists(String),
+  #[error("no such note: {0}")]
+  NoSuchNote(String),
+  #[error("the note is empty")]
+  Empty,
+}
+
+/// Lets the user edit the text with `$VISUAL` or `$EDITOR`, or `vi` if
+/// neither is set, and returns the edited text.
+pub fn edit(text: &str) -> Result<String, Error> {
+  let editor = ["VISUAL", "EDITOR"]
+    .into_iter()
+    .filter_map(env::var_os)
+    .find(|editor| !editor.is_empty())
+    .map_or("vi".to_owned(), |editor| {
+      editor.to_string_lossy().into_owned()
+    });
+  edit_with(&editor, text)
+}
+
+fn edit_with(editor: &str, text: &str) -> Result<String, Error> {
+  let dir = ScratchDir::create()?;
+  let path = dir.path.join("note.txt");
+  let mut file = fs::OpenOptions::new()
+    .write(true)
+    .create_new(true)
+    .mode(0o600)
+    .open(&path)?;
+  file.write_all(text.as_bytes())?;
+  drop(file);
+  // Run the editor through the shell like Git does so that the editor can
+  // come with arguments, e.g. "code --wait".
+  let status = Command::new("sh")
+    .arg("-c")
+    .arg(format!("{} \"$1\"", editor))
+    .arg(editor)
+    .arg(&path)
+    .status()?;
+  if !status.success() {
+    return Err(Error::EditorFailed);
+  }
+  Ok(fs::read_to_string(&path)?)
+}
+
+/// A private directory in memory that is shredded when dropped.
+struct ScratchDir {
+  path: PathBuf,
+}
+
+impl ScratchDir {
+  fn create() -> Result<Self, Error> {
+    let base = match env::var_os("XDG_RUNTIME_DIR") {
+      Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+      _ => PathBuf::from("/dev/shm"),
+    };
+    if !base.is_dir() {
+      return Err(Error::NoPrivateDir);
+    }
+    let path = base.join(format!("skele-note.{}", process::id()));
+    fs::DirBuilder::new().mode(0o700).create(&path)?;
+    Ok(Self { path })
+  }
+}
+
+impl Drop for ScratchDir {
+  fn drop(&mut self) {
+    let _ = shred_dir(&self.path);
+    let _ = fs::remove_dir_all(&self.path);
+  }
+}
+
+/// Overwrites the files in the directory with zeros and removes them.
+fn shred_dir(dir: &Path) -> io::Result<()> {
+  for entry in fs::read_dir(dir)? {
+    let entry = entry?;
+    if entry.file_type()?.is_file() {
+      let len = entry.metadata()?.len();
+      let mut file = fs::OpenOptions::new().write(true).open(entry.path())?;
+      io::copy(&mut io::repeat(0).take(len), &mut file)?;
+      file.sync_all()?;
+      fs::remove_file(entry.path())?;
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn test_edit_with() {
+    let edited = edit_with("sed -i s/old/new/", "old text\n").unwrap();
+    assert_eq!(edited, "new text\n");
+    assert!(matches!(
+      edit_with("false", "text"),
+      Err(Error::EditorFailed)
+    ));
+  }
+}
@@ -0,0 +1,573 @@
+//! # Machine-readable output
+//!
+//! With `--format json` every command writes its outcome to the standard
+//! output as JSON records, one per line, while everything meant for humans,
+//! prompts included, goes to the standard error.  The `record` field of each
+//! record tells its type.  The schema is stable: new fields may be added but
+//! the existing ones are neither removed nor renamed.
+//!
+//! - `{"record":"credentials","domain":"…","identity":"…","generation":0,
+//!   "fingerprint":"…","policy":"base32","password":"…"}` where `password` is
+//!   `null` unless it was revealed.
+//! - `{"record":"status","service":"agent","state":"listening","socket":"…"}`
+//!   where `service` is `agent` or `api` and `state` one of `listening`,
+//!   `locked`, `stopped`, and `not_running`.
+//! - `{"record":"totp","domain":"…","identity":"…","label":"…",
+//!   "state":"imported"}` where `state` is `imported` or `removed` tells the
+//!   outcome of managing the secrets issued by services.
+//! - `{"record":"tags","domain":"…","identity":"…","tags":["work"]}`.
+//! - `{"record":"export","path":"…","credentials":12,"passwords":false}` where
+//!   `credentials` is the number of credentials in the inventory and
+//!   `passwords` tells whether their passwords were included.
+//! - `{"record":"stored_password","domain":"…","identity":"…",
+//!   "state":"stored"}` where `state` is one of `stored`, `cleared`, and
+//!   `not_stored`.  The password itself is never included.
+//! - `{"record":"question","domain":"…","identity":"…","question":"…",
+//!   "answer":"…","state":"stored"}` where `state` is one of `stored`,
+//!   `removed`, and `not_found`, and `answer` is `null` unless the question is
+//!   stored.
+//! - `{"record":"note","title":"…","domain":"…","identity":"…","text":"…",
+//!   "state":"stored"}` where `state` is one of `stored`, `added`, `edited`,
+//!   `unchanged`, and `deleted`, `domain` and `identity` are `null` unless the
+//!   note belongs to credentials, and `text` is `null` unless the note was
+//!   viewed.
+//! - `{"record":"ssh_key","domain":"…","identity":"…","purpose":"ssh",
+//!   "public_key":"ssh-ed25519 …","path":"…"}` where `path` is that of the
+//!   private key file.
+//! - `{"record":"import","title":"…","domain":"…","identity":"…",
+//!   "state":"added","password":"differs"}` where `state` is one of `added`,
+//!   `known`, and `skipped`, and `password` one of `matches`, `differs`,
+//!   `stored`, and `missing` tells how the imported password compares with the
+//!   derived one.  Skipped entries lack a domain or an identity and have `null`
+//!   in place of them and of `password`.
+//! - `{"record":"breach","domain":"…","identity":"…","count":3}` where `count`
+//!   is the number of times the password has been seen in breaches, zero if
+//!   never.
+//! - `{"record":"file","operation":"encrypt","input":"…","output":"…"}` where
+//!   `operation` is `encrypt` or `decrypt`.
+//! - `{"record":"error","kind":"loading_key_state_failed","message":"…"}` is
+//!   written when a command fails; see [`ErrorKind`] for the kinds.
+
+use std::io::{self, Write};
+
+use rustybones::Policy;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+  agent, api, breach, cli, crypt, import, notes, serde_helpers::ser_display,
+  ssh, state, totp,
+};
+
+/// Failures that only make sense with the machine-readable output.
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("{0} has no machine-readable output")]
+  Unsupported(&'static str),
+  #[error("aborted by the user")]
+  Aborted,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+pub enum Record {
+  Credentials {
+    domain: String,
+    identity: String,
+    generation: usize,
+    fingerprint: String,
+    #[serde(serialize_with = "ser_display")]
+    policy: Policy,
+    password: Option<String>,
+  },
+  Status {
+    service: Service,
+    state: ServiceState,
+    socket: String,
+  },
+  Totp {
+    domain: String,
+    identity: String,
+    label: String,
+    state: TotpState,
+  },
+  Tags {
+    domain: String,
+    identity: String,
+    tags: Vec<String>,
+  },
+  Export {
+    path: String,
+    credentials: usize,
+    passwords: bool,
+  },
+  StoredPassword {
+    domain: String,
+    identity: String,
+    state: StoredPasswordState,
+  },
+  Question {
+    domain: String,
+    identity: String,
+    question: String,
+    answer: Option<String>,
+    state: QuestionState,
+  },
+  Note {
+    title: String,
+    domain: Option<String>,
+    identity: Option<String>,
+    text: Option<String>,
+    state: NoteState,
+  },
+  Import {
+    title: String,
+    domain: Option<String>,
+    identity: Option<String>,
+    state: ImportState,
+    password: Option<PasswordCheck>,
+  },
+  Breach {
+    domain: String,
+    identity: String,
+    count: u64,
+  },
+  File {
+    operation: FileOperation,
+    input: String,
+    output: String,
+  },
+  SshKey {
+    domain: String,
+    identity: String,
+    purpose: String,
+    public_key: String,
+    path: String,
+  },
+  Error {
+    kind: ErrorKind,
+    message: String,
+  },
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Service {
+  Agent,
+  Api,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceState {
+  Listening,
+  Locked,
+  Stopped,
+  NotRunning,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TotpState {
+  Imported,
+  Removed,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StoredPasswordState {
+  Stored,
+  Cleared,
+  NotStored,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuestionState {
+  Stored,
+  Removed,
+  NotFound,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoteState {
+  Stored,
+  Added,
+  Edited,
+  Unchanged,
+  Deleted,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportState {
+  Added,
+  Known,
+  Skipped,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PasswordCheck {
+  Matches,
+  Differs,
+  Stored,
+  Missing,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileOperation {
+  Encrypt,
+  Decrypt,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+  Io,
+  NoStateDir,
+  KdfOpFailed,
+  CipherOpFailed,
+  LoadingKeyStateFailed,
+  SavingKeyStateFailed,
+  WeakPolicy,
+  AgentAlreadyRunning,
+  AgentNotRunning,
+  AgentRefused,
+  InsecureSocketDir,
+  MalformedAgentMessage,
+  ApiAlreadyRunning,
+  InvalidTotpUri,
+  NoSuchTotpSecret,
+  SshKeyExists,
+  FileExists,
+  NoOutputPath,
+  MalformedFile,
+  WrongKey,
+  TamperedFile,
+  MalformedExport,
+  DecryptionFailed,
+  IncompleteBreachList,
+  MalformedBreachList,
+  NoPrivateDir,
+  EditorFailed,
+  NoteExists,
+  NoSuchNote,
+  EmptyNote,
+  Unsupported,
+  Aborted,
+  Other,
+}
+
+impl From<&state::Error> for ErrorKind {
+  fn from(err: &state::Error) -> Self {
+    match err {
+      state::Error::Io(_) => ErrorKind::Io,
+      state::Error::NoStateDir => ErrorKind::NoStateDir,
+      state::Error::KdfOpFailed => ErrorKind::KdfOpFailed,
+      state::Error::CipherOpFailed => ErrorKind::CipherOpFailed,
+      state::Error::LoadingKeyStateFailed => ErrorKind::LoadingKeyStateFailed,
+      state::Error::SavingKeyStateFailed => ErrorKind::SavingKeyStateFailed,
+    }
+  }
+}
+
+impl From<&cli::Error> for ErrorKind {
+  fn from(err: &cli::Error) -> Self {
+    match err {
+      cli::Error::WeakPolicy(..) => ErrorKind::WeakPolicy,
+    }
+  }
+}
+
+impl From<&agent::Error> for ErrorKind {
+  fn from(err: &agent::Error) -> Self {
+    match err {
+      agent::Error::Io(_) => ErrorKind::Io,
+      agent::Error::AlreadyRunning => ErrorKind::AgentAlreadyRunning,
+      agent::Error::NotRunning => ErrorKind::AgentNotRunning,
+      agent::Error::MalformedMessage => ErrorKind::MalformedAgentMessage,
+      agent::Error::Refused(_) => ErrorKind::AgentRefused,
+      agent::Error::InsecureDir(_) => ErrorKind::InsecureSocketDir,
+      agent::Error::State(err) => err.into(),
+    }
+  }
+}
+
+impl From<&api::Error> for ErrorKind {
+  fn from(err: &api::Error) -> Self {
+    match err {
+      api::Error::Io(_) | api::Error::Prompt(_) => ErrorKind::Io,
+      api::Error::AlreadyRunning => ErrorKind::ApiAlreadyRunning,
+      api::Error::Stopped => ErrorKind::Other,
+    }
+  }
+}
+
+impl From<&totp::Error> for ErrorKind {
+  fn from(err: &totp::Error) -> Self {
+    match err {
+      totp::Error::NoSuchSecret => ErrorKind::NoSuchTotpSecret,
+      _ => ErrorKind::InvalidTotpUri,
+    }
+  }
+}
+
+impl From<&ssh::Error> for ErrorKind {
+  fn from(err: &ssh::Error) -> Self {
+    match err {
+      ssh::Error::Io(_) => ErrorKind::Io,
+      ssh::Error::Exists(_) => ErrorKind::SshKeyExists,
+      ssh::Error::Encoding(_) => ErrorKind::Other,
+    }
+  }
+}
+
+impl From<&crypt::Error> for ErrorKind {
+  fn from(err: &crypt::Error) -> Self {
+    match err {
+      crypt::Error::Io(_) => ErrorKind::Io,
+      crypt::Error::Exists(_) => ErrorKind::FileExists,
+      crypt::Error::NoOutputPath => ErrorKind::NoOutputPath,
+      crypt::Error::Malformed => ErrorKind::MalformedFile,
+      crypt::Error::WrongKey(_) => ErrorKind::WrongKey,
+      crypt::Error::Tampered => ErrorKind::TamperedFile,
+      crypt::Error::CipherOpFailed => ErrorKind::CipherOpFailed,
+    }
+  }
+}
+
+impl From<&breach::Error> for ErrorKind {
+  fn from(err: &breach::Error) -> Self {
+    match err {
+      breach::Error::Io(_) => ErrorKind::Io,
+      breach::Error::MissingRange(_) => ErrorKind::IncompleteBreachList,
+      breach::Error::Malformed(_) => ErrorKind::MalformedBreachList,
+    }
+  }
+}
+
+impl From<&import::Error> for ErrorKind {
+  fn from(err: &import::Error) -> Self {
+    match err {
+      import::Error::Io(_) => ErrorKind::Io,
+      import::Error::Malformed(_) => ErrorKind::MalformedExport,
+      import::Error::Gpg(_) => ErrorKind::DecryptionFailed,
+    }
+  }
+}
+
+impl From<&notes::Error> for ErrorKind {
+  fn from(err: &notes::Error) -> Self {
+    match err {
+      notes::Error::Io(_) => ErrorKind::Io,
+      notes::Error::NoPrivateDir => ErrorKind::NoPrivateDir,
+      notes::Error::EditorFailed => ErrorKind::EditorFailed,
+      notes::Error::Exists(_) => ErrorKind::NoteExists,
+      notes::Error::NoSuchNote(_) => ErrorKind::NoSuchNote,
+      notes::Error::Empty => ErrorKind::EmptyNote,
+    }
+  }
+}
+
+impl From<&Error> for ErrorKind {
+  fn from(err: &Error) -> Self {
+    match err {
+      Error::Unsupported(_) => ErrorKind::Unsupported,
+      Error::Aborted => ErrorKind::Aborted,
+    }
+  }
+}
+
+impl From<&anyhow::Error> for ErrorKind {
+  fn from(err: &anyhow::Error) -> Self {
+    if let Some(err) = err.downcast_ref::<state::Error>() {
+      err.into()
+    } else if let Some(err) = err.downcast_ref::<cli::Error>() {
+      err.into()
+    } else if let Some(err) = err.downcast_ref::<agent::Error>() {
+      err.into()
+    } else if let Some(err) = err.downcast_ref::<api::Error>() {
+      err.into()
+    } else if let Some(err) = err.downcast_ref::<ssh::Error>() {
+      err.into()
+    } else if let Some(err) = err.downcast_ref::<totp::Error>() {
+      err.into()
+    } else if let Some(err) = err.downcast_ref::<crypt::Error>() {
+      err.into()
+    } else if let Some(err) = err.downcast_ref::<notes::Error>() {
+      err.into()
+    } else if let Some(err) = err.downcast_ref::<import::Error>() {
+      err.into()
+    } else if let Some(err) = err.downcast_ref::<breach::Error>() {
+      err.into()
+    } else if let Some(err) = err.downcast_ref::<Error>() {
+      err.into()
+    } else if err.downcast_ref::<io::Error>().is_some() {
+      ErrorKind::Io
+    } else {
+      ErrorKind::Other
+    }
+  }
+}
+
+impl Record {
+  pub fn error(err: &anyhow::Error) -> Self {
+    Record::Error {
+      kind: err.into(),
+      message: format!("{:#}", err),
+    }
+  }
+
+  /// Writes the record on a line of its own to the standard output.
+  pub fn print(&self) -> io::Result<()> {
+    let mut line = serde_json::to_vec(self).map_err(io::Error::other)?;
+    line.push(b'\n');
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(&line)?;
+    stdout.flush()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use std::{env, fs, path::PathBuf};
+
+  use super::*;
+
+  /// Compares the records, one per line, with the snapshot in `tests/data`.
+  /// Set `SKELE_UPDATE_SNAPSHOTS` to rewrite the snapshot instead.
+  fn assert_snapshot(name: &str, records: &[Record]) {
+    let actual = records
+      .iter()
+      .map(|r| serde_json::to_string(r).unwrap() + "\n")
+      .collect::<String>();
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+      .join("tests/data/snapshots")
+      .join(name);
+    if env::var_os("SKELE_UPDATE_SNAPSHOTS").is_some() {
+      fs::write(&path, &actual).unwrap();
+    }
+    assert_eq!(actual, fs::read_to_string(&path).unwrap());
+  }
+
+  #[test]
+  fn test_record_schema() {
+    assert_snapshot(
+      "records.jsonl",
+      &[
+        Record::Credentials {
+          domain: "example.com".to_owned(),
+          identity: "alice".to_owned(),
+          generation: 1,
+          fingerprint: "Abcd-Efgh-Jkmn-Pqrs".to_owned(),
+          policy: Policy::default(),
+          password: Some("5Wsc-X2mz-Csnc-4Vgc".to_owned()),
+        },
+        Record::Credentials {
+          domain: "example.com".to_owned(),
+          identity: "alice".to_owned(),
+          generation: 0,
+          fingerprint: "Abcd-Efgh-Jkmn-Pqrs".to_owned(),
+          policy: Policy::default(),
+          password: None,
+        },
+        Record::Status {
+          service: Service::Agent,
+          state: ServiceState::Listening,
+          socket: "/run/user/1000/skele/agent.sock".to_owned(),
+        },
+        Record::Status {
+          service: Service::Api,
+          state: ServiceState::NotRunning,
+          socket: "/run/user/1000/skele/api.sock".to_owned(),
+        },
+        Record::Totp {
+          domain: "example.com".to_owned(),
+          identity: "alice".to_owned(),
+          label: "Example:alice@example.com".to_owned(),
+          state: TotpState::Imported,
+        },
+        Record::Tags {
+          domain: "example.com".to_owned(),
+          identity: "alice".to_owned(),
+          tags: vec!["shared".to_owned(), "work".to_owned()],
+        },
+        Record::Export {
+          path: "inventory.csv".to_owned(),
+          credentials: 12,
+          passwords: false,
+        },
+        Record::StoredPassword {
+          domain: "vendor.example".to_owned(),
+          identity: "team".to_owned(),
+          state: StoredPasswordState::Stored,
+        },
+        Record::Question {
+          domain: "example.com".to_owned(),
+          identity: "alice".to_owned(),
+          question: "What is your mother's maiden name?".to_owned(),
+          answer: Some("suggest ice second swear".to_owned()),
+          state: QuestionState::Stored,
+        },
+        Record::Note {
+          title: "GitHub recovery codes".to_owned(),
+          domain: Some("github.com".to_owned()),
+          identity: Some("alice".to_owned()),
+          text: Some("1a2b3-c4d5e\n6f7a8-b9c0d\n".to_owned()),
+          state: NoteState::Stored,
+        },
+        Record::Import {
+          title: "GitHub".to_owned(),
+          domain: Some("github.com".to_owned()),
+          identity: Some("alice".to_owned()),
+          state: ImportState::Added,
+          password: Some(PasswordCheck::Differs),
+        },
+        Record::Breach {
+          domain: "example.com".to_owned(),
+          identity: "alice".to_owned(),
+          count: 3,
+        },
+        Record::File {
+          operation: FileOperation::Encrypt,
+          input: "recovery-codes.txt".to_owned(),
+          output: "recovery-codes.txt.skele".to_owned(),
+        },
+        Record::SshKey {
+          domain: "github.com".to_owned(),
+          identity: "alice".to_owned(),
+          purpose: "ssh".to_owned(),
+          public_key: "ssh-ed25519 AAAA… alice@github.com".to_owned(),
+          path: "/home/alice/.ssh/id_ed25519".to_owned(),
+        },
+      ],
+    );
+  }
+
+  #[test]
+  fn test_state_error_records() {
+    let errors = [
+      state::Error::Io(io::Error::other("disk on fire")),
+      state::Error::NoStateDir,
+      state::Error::KdfOpFailed,
+      state::Error::CipherOpFailed,
+      state::Error::LoadingKeyStateFailed,
+      state::Error::SavingKeyStateFailed,
+    ];
+    let records = errors
+      .into_iter()
+      .map(|err| Record::error(&err.into()))
+      .collect::<Vec<_>>();
+    assert_snapshot("state_errors.jsonl", &records);
+  }
+
+  #[test]
+  fn test_cli_error_records() {
+    let records = [Record::error(&cli::Error::WeakPolicy(29.9, 40).into())];
+    assert_snapshot("cli_errors.jsonl", &records);
+  }
+}
@@ -104,3 +104,28 @@ where
     marker: PhantomData,
   })
 }
+
+pub fn de_from_str<'de, T, D>(
+  deserializer: D,
+) -> std::result::Result<T, D::Error>
+where
+  D: Deserializer<'de>,
+  T: std::str::FromStr,
+  T::Err: std::fmt::Display,
+{
+  use serde::{de, Deserialize};
+
+  let s = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+  s.parse().map_err(de::Error::custom)
+}
+
+pub fn ser_display<T, S>(
+  value: &T,
+  serializer: S,
+) -> std::result::Result<S::Ok, S::Error>
+where
+  S: Serializer,
+  T: std::fmt::Display,
+{
+  serializer.collect_str(value)
+}
@@ -0,0 +1,150 @@
+//! # OpenSSH keys
+//!
+//! Writes the Ed25519 key pairs derived from the skeleton key as OpenSSH key
+//! files.  The same skeleton key, domain, identity, and purpose always give
+//! the same key pair so a lost key can be written anew anywhere.  Only the
+//! passphrase-protected private key file differs from one write to another as
+//! its encryption is salted.
+
+use std::{
+  fs,
+  io::{self, Write},
+  os::unix::fs::OpenOptionsExt,
+  path::{Path, PathBuf},
+};
+
+use rustybones::ED25519_SEED_SIZE;
+use ssh_key::{private::Ed25519Keypair, LineEnding, PrivateKey};
+use thiserror::Error;
+
+use crate::types::Passphrase;
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("i/o error")]
+  Io(#[from] io::Error),
+  #[error("{0} exists already")]
+  Exists(PathBuf),
+  #[error("failed to encode the key")]
+  Encoding(#[from] ssh_key::Error),
+}
+
+/// Returns the key pair with the given seed.
+pub fn private_key(
+  seed: &[u8; ED25519_SEED_SIZE],
+  comment: &str,
+) -> Result<PrivateKey, Error> {
+  let keypair = Ed25519Keypair::from_seed(seed);
+  Ok(PrivateKey::new(keypair.into(), comment)?)
+}
+
+/// Returns the path of the public key file that goes with the private key
+/// file.
+pub fn public_key_path(path: &Path) -> PathBuf {
+  let mut name = path.file_name().unwrap_or_default().to_owned();
+  name.push(".pub");
+  path.with_file_name(name)
+}
+
+/// Writes the private key to `path`, encrypted if a passphrase is given, and
+/// the public key next to it (see [`public_key_path`]).  Refuses to replace
+/// existing files unless `overwrite` is set.
+pub fn write(
+  key: &PrivateKey,
+  path: &Path,
+  passphrase: Option<&Passphrase>,
+  overwrite: bool,
+) -> Result<(), Error> {
+  let public_path = public_key_path(path);
+  if !overwrite {
+    for path in [path, &public_path] {
+      if path.exists() {
+        return Err(Error::Exists(path.to_owned()));
+      }
+    }
+  }
+  let private = match passphrase {
+    Some(passphrase) => {
+      key.encrypt(&mut rand_core::OsRng, passphrase.as_bytes())?
+    }
+    None => key.clone(),
+  };
+  let private = private.to_openssh(LineEnding::LF)?;
+  write_file(path, private.as_bytes(), 0o600)?;
+  let public = key.public_key().to_openssh()? + "\n";
+  write_file(&public_path, public.as_bytes(), 0o644)?;
+  Ok(())
+}
+
+fn write_file(path: &Path, contents: &[u8], mode: u32) -> io::Result<()> {
+  // Remove the old file first so that the mode of a new one applies.
+  match fs::remove_file(path) {
+    Ok(()) => {}
+    Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+    Err(err) => return Err(err),
+  }
+  let mut file = fs::OpenOptions::new()
+    .write(true)
+    .create_new(true)
+    .mode(mode)
+    .open(path)?;
+  file.write_all(contents)?;
+  file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+
+  use std::{env, process};
+
+  use rustybones::KeySource;
+
+  use super::*;
+
+  // Written with this module and checked with `ssh-keygen -y -f id_ed25519`
+  // to agree with OpenSSH.
+  const PRIVATE_KEY: &str = include_str!("../tests/data/ssh/id_ed25519");
+  const PUBLIC_KEY: &str = include_str!("../tests/data/ssh/id_ed25519.pub");
+
+  fn test_key() -> PrivateKey {
+    let seed = KeySource::new("secret skeleton passphrase").ed25519_seed(
+      "github.com",
+      "alice",
+      "ssh",
+    );
+    private_key(&seed, "alice@github.com").unwrap()
+  }
+
+  #[test]
+  fn test_vectors() {
+    let key = test_key();
+    assert_eq!(
+      key.to_openssh(LineEnding::LF).unwrap().as_str(),
+      PRIVATE_KEY
+    );
+    assert_eq!(key.public_key().to_openssh().unwrap() + "\n", PUBLIC_KEY);
+  }
+
+  #[test]
+  fn test_write_with_passphrase() {
+    let dir = env::temp_dir().join(format!("skele-test-{}-ssh", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("id_ed25519");
+    let key = test_key();
+    let passphrase = Passphrase::from("correct horse");
+    write(&key, &path, Some(&passphrase), false).unwrap();
+    assert!(matches!(
+      write(&key, &path, None, false),
+      Err(Error::Exists(_))
+    ));
+
+    let written = PrivateKey::read_openssh_file(&path).unwrap();
+    assert!(written.is_encrypted());
+    assert_eq!(written.decrypt(passphrase.as_bytes()).unwrap(), key);
+    assert_eq!(
+      fs::read_to_string(public_key_path(&path)).unwrap(),
+      PUBLIC_KEY
+    );
+    let _ = fs::remove_dir_all(dir);
+  }
+}
@@ -1,16 +1,22 @@
 //! # Application state
 
 use std::{
-  fs, io,
+  env, fs, io,
   path::{Path, PathBuf},
   process,
+  time::{SystemTime, UNIX_EPOCH},
 };
 
 use directories_next::ProjectDirs;
+use rustybones::Policy;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::{ciphered, kdf, types::Passphrase};
+use crate::{
+  ciphered, kdf,
+  serde_helpers::{de_from_str, ser_display},
+  types::Passphrase,
+};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -46,7 +52,7 @@ impl From<ciphered::Error> for Error {
 
 /// Ensures that the application state directory exists and returns the path
 /// to it.
-fn ensure_state_dir() -> Result<PathBuf, Error> {
+pub fn ensure_state_dir() -> Result<PathBuf, Error> {
   let project_dirs =
     ProjectDirs::from("com", "mjhanninen", "skele").ok_or(Error::NoStateDir)?;
   let dir = project_dirs.data_local_dir();
@@ -60,6 +66,16 @@ fn ensure_state_dir() -> Result<PathBuf, Error> {
   }
 }
 
+/// Returns the directory for runtime files such as sockets.  This is the
+/// per-user runtime directory when there is one and the state directory
+/// otherwise.
+pub fn runtime_dir() -> Result<PathBuf, Error> {
+  match env::var_os("XDG_RUNTIME_DIR") {
+    Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir).join("skele")),
+    _ => ensure_state_dir(),
+  }
+}
+
 pub struct AppState {
   state_dir: Box<Path>,
 }
@@ -91,18 +107,69 @@ impl AppState {
     skeleton_key: &Passphrase,
     fingerprint: &str,
   ) -> Result<KeyState, Error> {
+    let state = self.read_key_state(fingerprint)?.decrypt(skeleton_key)?;
+    Ok(state)
+  }
+
+  /// Replaces the key state with the one on the disk so that the changes made
+  /// by other skele processes show.  The state is deciphered with the key it
+  /// already holds.
+  pub fn reload_key_state(
+    &self,
+    key_state: &mut KeyState,
+  ) -> Result<(), Error> {
+    let encrypted = self.read_key_state(&key_state.public.fingerprint)?;
+    *key_state = encrypted.decrypt_with_key(key_state.key)?;
+    Ok(())
+  }
+
+  /// Reloads the key state, applies the change to it, and saves it while
+  /// holding the lock of the state file.  Processes that keep the state for
+  /// long, such as the agent, change it only this way so that they do not
+  /// overwrite the changes made by others meanwhile.
+  pub fn update_key_state<T>(
+    &self,
+    key_state: &mut KeyState,
+    change: impl FnOnce(&mut KeyState) -> T,
+  ) -> Result<T, Error> {
+    let _lock = self.lock_key_state(&key_state.public.fingerprint)?;
+    self.reload_key_state(key_state)?;
+    let result = change(key_state);
+    self.write_key_state(key_state)?;
+    Ok(result)
+  }
+
+  pub fn save_key_state(&self, key_state: &KeyState) -> Result<(), Error> {
+    let _lock = self.lock_key_state(&key_state.public.fingerprint)?;
+    self.write_key_state(key_state)
+  }
+
+  fn read_key_state(
+    &self,
+    fingerprint: &str,
+  ) -> Result<EncryptedKeyState, Error> {
     let path = self.key_state_path(fingerprint, false);
     let file = fs::File::open(path)?;
     let reader = io::BufReader::new(file);
-    let Ok(encrypted) = serde_json::from_reader::<_, EncryptedKeyState>(reader)
-    else {
-      return Err(Error::LoadingKeyStateFailed);
-    };
-    let state = encrypted.decrypt(skeleton_key)?;
-    Ok(state)
+    serde_json::from_reader::<_, EncryptedKeyState>(reader)
+      .map_err(|_| Error::LoadingKeyStateFailed)
   }
 
-  pub fn save_key_state(&self, key_state: &KeyState) -> Result<(), Error> {
+  /// Takes the lock that serializes the writers of the state file.  The lock
+  /// is released when the returned file is dropped.
+  fn lock_key_state(&self, fingerprint: &str) -> Result<fs::File, Error> {
+    let mut path = PathBuf::from(self.state_dir.as_ref());
+    path.push(format!("{}.lock", fingerprint));
+    let file = fs::OpenOptions::new()
+      .create(true)
+      .truncate(false)
+      .write(true)
+      .open(path)?;
+    file.lock()?;
+    Ok(file)
+  }
+
+  fn write_key_state(&self, key_state: &KeyState) -> Result<(), Error> {
     let temp_path = self.key_state_path(&key_state.public.fingerprint, true);
     {
       let encrypted = key_state.encrypt()?;
@@ -153,6 +220,38 @@ impl PublicKeyState {
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct SecretKeyState {
   pub credentials: Vec<Credentials>,
+  #[serde(default)]
+  pub notes: Vec<SecretNote>,
+}
+
+impl SecretKeyState {
+  /// Brings the state read from an older state file up to date.
+  fn migrated(mut self) -> Self {
+    let now = unix_time();
+    for c in self.credentials.iter_mut() {
+      // Before frecency was introduced only the number of uses was tracked.
+      // Use that as the initial score and start decaying it from now on.
+      if c.score == 0.0 && c.count > 0 {
+        c.score = c.count as f64;
+      }
+      if c.last_used == 0 {
+        c.last_used = now;
+      }
+    }
+    self
+  }
+
+  /// Returns the credentials ordered by descending frecency.  The ties are
+  /// broken by the label.
+  pub fn ranked(&self, now: u64) -> Vec<&Credentials> {
+    let mut ranked = self.credentials.iter().collect::<Vec<_>>();
+    ranked.sort_by(|l, r| {
+      r.frecency(now)
+        .total_cmp(&l.frecency(now))
+        .then_with(|| l.label().cmp(&r.label()))
+    });
+    ranked
+  }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -182,40 +281,735 @@ impl KeyState {
     })
   }
 
+  pub fn find(&self, domain: &str, identity: &str) -> Option<&Credentials> {
+    self
+      .secret
+      .credentials
+      .iter()
+      .find(|c| c.domain.as_ref() == domain && c.identity.as_ref() == identity)
+  }
+
+  pub fn find_mut(
+    &mut self,
+    domain: &str,
+    identity: &str,
+  ) -> Option<&mut Credentials> {
+    self
+      .secret
+      .credentials
+      .iter_mut()
+      .find(|c| c.domain.as_ref() == domain && c.identity.as_ref() == identity)
+  }
+
+  /// Returns the generation of the password currently in use for the pair.
+  pub fn generation(&self, domain: &str, identity: &str) -> usize {
+    self.find(domain, identity).map_or(0, |c| c.generation)
+  }
+
+  /// Returns the generation a client asking for the password of the pair
+  /// gets: the one asked for or, if none, the one in use, so that clients
+  /// follow the rotations.  Returns `None` if the one asked for is above
+  /// [`MAX_GENERATION`].
+  pub fn requested_generation(
+    &self,
+    domain: &str,
+    identity: &str,
+    requested: Option<usize>,
+  ) -> Option<usize> {
+    match requested {
+      Some(generation) if generation > MAX_GENERATION => None,
+      Some(generation) => Some(generation),
+      None => Some(self.generation(domain, identity)),
+    }
+  }
+
+  /// Moves the pair on to the next generation of the password and returns it.
+  /// Returns `None` if the pair is not known.
+  pub fn rotate(&mut self, domain: &str, identity: &str) -> Option<usize> {
+    let c = self.find_mut(domain, identity)?;
+    c.generation += 1;
+    Some(c.generation)
+  }
+
+  /// Returns the credentials of the pair remembering the pair if it was not
+  /// known.  Unlike `touch` this does not count as a use.
+  fn entry(&mut self, domain: &str, identity: &str) -> &mut Credentials {
+    if self.find(domain, identity).is_none() {
+      self
+        .secret
+        .credentials
+        .push(Credentials::new(domain, identity));
+    }
+    self.find_mut(domain, identity).unwrap()
+  }
+
+  /// Returns the password policy of the pair.
+  pub fn policy(&self, domain: &str, identity: &str) -> Policy {
+    self
+      .find(domain, identity)
+      .map_or(Policy::default(), |c| c.policy)
+  }
+
+  /// Sets the password policy of the pair remembering the pair if it was not
+  /// known.
+  pub fn set_policy(&mut self, domain: &str, identity: &str, policy: Policy) {
+    self.entry(domain, identity).policy = policy;
+  }
+
+  /// Stores the `otpauth://` URI of a secret issued for the pair remembering
+  /// the pair if it was not known.  Returns `false` if the URI was stored
+  /// already.
+  pub fn add_totp_uri(
+    &mut self,
+    domain: &str,
+    identity: &str,
+    uri: &str,
+  ) -> bool {
+    let c = self.entry(domain, identity);
+    if c.totp_uris.iter().any(|u| u.as_ref() == uri) {
+      return false;
+    }
+    c.totp_uris.push(uri.into());
+    true
+  }
+
+  /// Removes the `index`th stored `otpauth://` URI of the pair and returns
+  /// it.
+  pub fn remove_totp_uri(
+    &mut self,
+    domain: &str,
+    identity: &str,
+    index: usize,
+  ) -> Option<Box<str>> {
+    let c = self.find_mut(domain, identity)?;
+    (index < c.totp_uris.len()).then(|| c.totp_uris.remove(index))
+  }
+
+  /// Stores the security question of the pair remembering the pair if it was
+  /// not known.  Returns `false` if the question was stored already, perhaps
+  /// phrased a little differently.
+  pub fn add_question(
+    &mut self,
+    domain: &str,
+    identity: &str,
+    question: &str,
+  ) -> bool {
+    let c = self.entry(domain, identity);
+    if c.find_question(question).is_some() {
+      return false;
+    }
+    c.questions.push(question.into());
+    true
+  }
+
+  /// Removes the security question of the pair and returns it as it was
+  /// stored.
+  pub fn remove_question(
+    &mut self,
+    domain: &str,
+    identity: &str,
+    question: &str,
+  ) -> Option<Box<str>> {
+    let c = self.find_mut(domain, identity)?;
+    let index = c.find_question(question)?;
+    Some(c.questions.remove(index))
+  }
+
+  /// Returns the note with the given title.
+  pub fn find_note(&self, title: &str) -> Option<&SecretNote> {
+    self.secret.notes.iter().find(|n| n.title.as_ref() == title)
+  }
+
+  /// Returns the notes that belong to the pair.
+  pub fn notes_of<'a>(
+    &'a self,
+    domain: &'a str,
+    identity: &'a str,
+  ) -> impl Iterator<Item = &'a SecretNote> {
+    self.secret.notes.iter().filter(move |n| {
+      n.domain.as_deref() == Some(domain)
+        && n.identity.as_deref() == Some(identity)
+    })
+  }
+
+  /// Stores a new note that may belong to a domain-identity pair.  Returns
+  /// `false` if there is a note with the same title already.
+  pub fn add_note(
+    &mut self,
+    title: &str,
+    credentials: Option<(&str, &str)>,
+    text: &str,
+  ) -> bool {
+    if self.find_note(title).is_some() {
+      return false;
+    }
+    self.secret.notes.push(SecretNote {
+      title: title.into(),
+      domain: credentials.map(|(domain, _)| domain.into()),
+      identity: credentials.map(|(_, identity)| identity.into()),
+      text: text.into(),
+      modified: unix_time(),
+    });
+    true
+  }
+
+  /// Replaces the text of the note.  Returns `false` if there is no such
+  /// note.
+  pub fn set_note_text(&mut self, title: &str, text: &str) -> bool {
+    let Some(note) = self
+      .secret
+      .notes
+      .iter_mut()
+      .find(|n| n.title.as_ref() == title)
+    else {
+      return false;
+    };
+    note.text = text.into();
+    note.modified = unix_time();
+    true
+  }
+
+  /// Replaces the text of the note that is titled after the pair (see
+  /// [`Credentials::label`]) and belongs to it, adding the note if there is
+  /// none and removing it if the text is empty.  This is the note the TUI
+  /// edits.
+  pub fn set_pair_note(&mut self, domain: &str, identity: &str, text: &str) {
+    let title = pair_note_title(domain, identity);
+    if text.trim().is_empty() {
+      self.remove_note(&title);
+    } else if !self.set_note_text(&title, text) {
+      self.add_note(&title, Some((domain, identity)), text);
+    }
+  }
+
+  /// Removes the note and returns it, or `None` if there is no such note.
+  pub fn remove_note(&mut self, title: &str) -> Option<SecretNote> {
+    let index = self
+      .secret
+      .notes
+      .iter()
+      .position(|n| n.title.as_ref() == title)?;
+    Some(self.secret.notes.remove(index))
+  }
+
+  /// Remembers the pair read from another password manager without counting
+  /// it as a use.  The notes are kept as the note of the pair (see
+  /// [`KeyState::set_pair_note`]) unless the pair has notes already.  Returns
+  /// `true` iff the pair was not known.
+  pub fn import(
+    &mut self,
+    domain: &str,
+    identity: &str,
+    notes: Option<&str>,
+  ) -> bool {
+    let known = self.find(domain, identity).is_some();
+    self.entry(domain, identity);
+    if let Some(notes) = notes {
+      if self.notes_of(domain, identity).next().is_none() {
+        self.set_pair_note(domain, identity, notes);
+      }
+    }
+    !known
+  }
+
+  /// Returns the tags of the pair.
+  pub fn tags(&self, domain: &str, identity: &str) -> &[Box<str>] {
+    self.find(domain, identity).map_or(&[], |c| &c.tags)
+  }
+
+  /// Replaces the tags of the pair remembering the pair if it was not known.
+  /// The tags are kept sorted and without duplicates.
+  pub fn set_tags(&mut self, domain: &str, identity: &str, tags: &[String]) {
+    let mut tags = tags
+      .iter()
+      .map(|tag| tag.trim())
+      .filter(|tag| !tag.is_empty())
+      .map(Into::into)
+      .collect::<Vec<Box<str>>>();
+    tags.sort();
+    tags.dedup();
+    self.entry(domain, identity).tags = tags;
+  }
+
+  /// Returns the password stored for the pair, if any.
+  pub fn stored_password(&self, domain: &str, identity: &str) -> Option<&str> {
+    self.find(domain, identity)?.stored_password.as_deref()
+  }
+
+  /// Stores the password to be used instead of the derived one remembering
+  /// the pair if it was not known.
+  pub fn set_stored_password(
+    &mut self,
+    domain: &str,
+    identity: &str,
+    password: &str,
+  ) {
+    self.entry(domain, identity).stored_password = Some(password.into());
+  }
+
+  /// Removes the stored password of the pair so that the derived one is used
+  /// again.  Returns `true` iff there was a stored password.
+  pub fn clear_stored_password(
+    &mut self,
+    domain: &str,
+    identity: &str,
+  ) -> bool {
+    self
+      .find_mut(domain, identity)
+      .and_then(|c| c.stored_password.take())
+      .is_some()
+  }
+
+  /// Forgets the pair and removes its notes.  Returns `true` iff the pair was
+  /// known.
+  pub fn forget(&mut self, domain: &str, identity: &str) -> bool {
+    let len = self.secret.credentials.len();
+    self.secret.credentials.retain(|c| {
+      c.domain.as_ref() != domain || c.identity.as_ref() != identity
+    });
+    self.secret.notes.retain(|n| {
+      n.domain.as_deref() != Some(domain)
+        || n.identity.as_deref() != Some(identity)
+    });
+    self.secret.credentials.len() != len
+  }
+
   /// Memorizes the given domain-identity pair.  Returns `true` iff the state
   /// did not know the pair already.
   pub fn touch(&mut self, domain: &str, identity: &str) {
-    if let Some(c) =
-      self.secret.credentials.iter_mut().find(|c| {
-        c.domain.as_ref() == domain && c.identity.as_ref() == identity
-      })
-    {
-      c.count += 1;
+    let now = unix_time();
+    if let Some(c) = self.find_mut(domain, identity) {
+      c.record_use(now);
     } else {
-      self.secret.credentials.push(Credentials {
-        domain: domain.into(),
-        identity: identity.into(),
-        count: 1,
-      });
+      let mut c = Credentials::new(domain, identity);
+      c.record_use(now);
+      self.secret.credentials.push(c);
     }
-    self.secret.credentials.sort_by_cached_key(|c| c.count);
+    self
+      .secret
+      .credentials
+      .sort_by(|l, r| r.frecency(now).total_cmp(&l.frecency(now)));
   }
 }
 
 impl EncryptedKeyState {
   pub fn decrypt(self, skeleton_key: &Passphrase) -> Result<KeyState, Error> {
     let key = self.public.kdf.derive(skeleton_key)?;
+    self.decrypt_with_key(key)
+  }
+
+  /// Deciphers the state with the key derived from the skeleton key already.
+  pub fn decrypt_with_key(self, key: [u8; 32]) -> Result<KeyState, Error> {
     Ok(KeyState {
       key,
       public: self.public,
-      secret: self.secret.decipher(&key)?,
+      secret: self.secret.decipher::<SecretKeyState>(&key)?.migrated(),
     })
   }
 }
 
+/// The highest generation of a password a client may ask for.  The earlier
+/// generations are walked through to get to a generation.
+pub const MAX_GENERATION: usize = 1000;
+
+/// The time it takes for the usage score to decay to half, in seconds.
+const FRECENCY_HALF_LIFE: f64 = 30.0 * 24.0 * 60.0 * 60.0;
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Credentials {
   pub domain: Box<str>,
   pub identity: Box<str>,
-  pub count: u16,
+  /// The total number of uses.  Saturates instead of overflowing.
+  pub count: u32,
+  /// The time of the last use in seconds since the Unix epoch.
+  #[serde(default)]
+  pub last_used: u64,
+  /// The usage score as it stood at the time of the last use.  Every use adds
+  /// one to the score and the score halves every `FRECENCY_HALF_LIFE`.
+  #[serde(default)]
+  pub score: f64,
+  /// The generation of the password currently in use.  Rotating the password
+  /// moves on to the next generation.
+  #[serde(default)]
+  pub generation: usize,
+  /// How the keys are turned into the password.
+  #[serde(
+    default,
+    serialize_with = "ser_display",
+    deserialize_with = "de_from_str"
+  )]
+  pub policy: Policy,
+  /// The `otpauth://` URIs of the one-time password secrets issued by the
+  /// service, as opposed to the derived one.
+  #[serde(default)]
+  pub totp_uris: Vec<Box<str>>,
+  /// The security questions the service asked.  The answers are derived.
+  #[serde(default)]
+  pub questions: Vec<Box<str>>,
+  /// Free-form labels for grouping the credentials, e.g. `work`.
+  #[serde(default)]
+  pub tags: Vec<Box<str>>,
+  /// The password used instead of the derived one for accounts whose
+  /// password cannot be changed, e.g. shared or vendor-issued ones.
+  #[serde(default)]
+  pub stored_password: Option<Box<str>>,
+}
+
+impl Credentials {
+  pub fn new(domain: &str, identity: &str) -> Self {
+    Self {
+      domain: domain.into(),
+      identity: identity.into(),
+      count: 0,
+      last_used: 0,
+      score: 0.0,
+      generation: 0,
+      policy: Policy::default(),
+      totp_uris: Vec::new(),
+      questions: Vec::new(),
+      tags: Vec::new(),
+      stored_password: None,
+    }
+  }
+
+  /// Returns the usage score decayed to the time `now`.
+  pub fn frecency(&self, now: u64) -> f64 {
+    let age = now.saturating_sub(self.last_used) as f64;
+    self.score * (-age / FRECENCY_HALF_LIFE).exp2()
+  }
+
+  /// Records a use of the credentials at the time `now`.
+  pub fn record_use(&mut self, now: u64) {
+    self.score = self.frecency(now) + 1.0;
+    self.count = self.count.saturating_add(1);
+    self.last_used = self.last_used.max(now);
+  }
+
+  /// Returns the index of the stored question that normalizes the same as
+  /// the given one.
+  fn find_question(&self, question: &str) -> Option<usize> {
+    let question = rustybones::normalize_question(question);
+    self
+      .questions
+      .iter()
+      .position(|q| rustybones::normalize_question(q) == question)
+  }
+
+  /// Returns the credentials in the "identity @ domain" form used when
+  /// searching.
+  pub fn label(&self) -> String {
+    format!("{} @ {}", self.identity, self.domain)
+  }
+
+  /// Parses the "identity @ domain" form back into a domain-identity pair.
+  pub fn parse_label(label: &str) -> Option<(&str, &str)> {
+    let (identity, domain) = label.rsplit_once(" @ ")?;
+    let (identity, domain) = (identity.trim(), domain.trim());
+    if identity.is_empty() || domain.is_empty() {
+      None
+    } else {
+      Some((domain, identity))
+    }
+  }
+}
+
+/// Returns the title of the note kept for the pair (see
+/// [`KeyState::set_pair_note`]), i.e. the label of the credentials.
+pub fn pair_note_title(domain: &str, identity: &str) -> String {
+  format!("{} @ {}", identity, domain)
+}
+
+/// A secret that cannot be derived, such as recovery codes or a password
+/// chosen by someone else.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SecretNote {
+  /// The title that tells the notes apart.
+  pub title: Box<str>,
+  /// The domain-identity pair the note belongs to, if any.
+  #[serde(default)]
+  pub domain: Option<Box<str>>,
+  #[serde(default)]
+  pub identity: Option<Box<str>>,
+  pub text: Box<str>,
+  /// The time of the last change in seconds since the Unix epoch.
+  pub modified: u64,
+}
+
+/// Returns the current time in seconds since the Unix epoch.
+pub fn unix_time() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  const DAY: u64 = 24 * 60 * 60;
+
+  /// Returns an empty key state with a made-up key and fingerprint.
+  fn test_state() -> KeyState {
+    KeyState {
+      key: [0u8; 32],
+      public: PublicKeyState::new("Abcd-Efgh", kdf::Kdf::default()),
+      secret: SecretKeyState::default(),
+    }
+  }
+
+  #[test]
+  fn test_frecency_decays() {
+    let mut c = Credentials::new("example.com", "alice");
+    c.record_use(100 * DAY);
+    c.record_use(100 * DAY);
+    assert_eq!(c.frecency(100 * DAY), 2.0);
+    assert_eq!(c.frecency(130 * DAY), 1.0);
+    assert!(c.frecency(1000 * DAY) < 1e-6);
+  }
+
+  #[test]
+  fn test_recent_use_outranks_old_heavy_use() {
+    let mut old = Credentials::new("example.com", "alice");
+    for _ in 0..20 {
+      old.record_use(0);
+    }
+    let mut recent = Credentials::new("example.com", "bob");
+    for _ in 0..3 {
+      recent.record_use(365 * DAY);
+    }
+    assert!(recent.frecency(365 * DAY) > old.frecency(365 * DAY));
+  }
+
+  #[test]
+  fn test_use_count_saturates() {
+    let mut c = Credentials::new("example.com", "alice");
+    c.count = u32::MAX;
+    c.record_use(DAY);
+    assert_eq!(c.count, u32::MAX);
+  }
+
+  #[test]
+  fn test_rotate_and_forget() {
+    let mut state = test_state();
+    state.touch("example.com", "alice");
+    assert_eq!(state.generation("example.com", "alice"), 0);
+    assert_eq!(state.rotate("example.com", "alice"), Some(1));
+    assert_eq!(state.generation("example.com", "alice"), 1);
+    assert_eq!(state.rotate("example.com", "bob"), None);
+    assert!(state.forget("example.com", "alice"));
+    assert!(!state.forget("example.com", "alice"));
+    assert_eq!(state.generation("example.com", "alice"), 0);
+  }
+
+  #[test]
+  fn test_requested_generation() {
+    let mut state = test_state();
+    assert_eq!(
+      state.requested_generation("example.com", "alice", None),
+      Some(0)
+    );
+    state.touch("example.com", "alice");
+    state.rotate("example.com", "alice");
+    assert_eq!(
+      state.requested_generation("example.com", "alice", None),
+      Some(1)
+    );
+    assert_eq!(
+      state.requested_generation("example.com", "alice", Some(0)),
+      Some(0)
+    );
+    assert_eq!(
+      state.requested_generation("example.com", "alice", Some(MAX_GENERATION)),
+      Some(MAX_GENERATION)
+    );
+    assert_eq!(
+      state.requested_generation(
+        "example.com",
+        "alice",
+        Some(MAX_GENERATION + 1)
+      ),
+      None
+    );
+  }
+
+  #[test]
+  fn test_update_key_state() {
+    let dir =
+      env::temp_dir().join(format!("skele-test-state.{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let app_state = AppState {
+      state_dir: dir.clone().into_boxed_path(),
+    };
+    let mut stale = test_state();
+    app_state.save_key_state(&stale).unwrap();
+    // Another invocation changes the state in the meantime.
+    let mut other = test_state();
+    other.set_policy("example.com", "alice", "pin".parse().unwrap());
+    app_state.save_key_state(&other).unwrap();
+    app_state
+      .update_key_state(&mut stale, |state| state.touch("example.com", "bob"))
+      .unwrap();
+    assert_eq!(stale.secret.credentials.len(), 2);
+    let mut saved = test_state();
+    app_state.reload_key_state(&mut saved).unwrap();
+    assert_eq!(saved.policy("example.com", "alice").to_string(), "pin:4");
+    assert_eq!(saved.find("example.com", "bob").unwrap().count, 1);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn test_totp_uris() {
+    let mut state = test_state();
+    let uri = "otpauth://totp/x?secret=MZXW6YTB";
+    assert!(state.add_totp_uri("example.com", "alice", uri));
+    assert!(!state.add_totp_uri("example.com", "alice", uri));
+    let c = state.find("example.com", "alice").unwrap();
+    assert_eq!(c.count, 0);
+    assert_eq!(c.totp_uris.len(), 1);
+    assert_eq!(state.remove_totp_uri("example.com", "alice", 1), None);
+    assert_eq!(
+      state.remove_totp_uri("example.com", "alice", 0).as_deref(),
+      Some(uri)
+    );
+    assert_eq!(state.remove_totp_uri("example.com", "bob", 0), None);
+  }
+
+  #[test]
+  fn test_questions() {
+    let mut state = test_state();
+    assert!(state.add_question("example.com", "alice", "First pet?"));
+    assert!(!state.add_question("example.com", "alice", "first  PET"));
+    assert_eq!(
+      state.remove_question("example.com", "alice", "Last pet"),
+      None
+    );
+    assert_eq!(
+      state
+        .remove_question("example.com", "alice", "first pet")
+        .as_deref(),
+      Some("First pet?")
+    );
+    assert!(state
+      .find("example.com", "alice")
+      .unwrap()
+      .questions
+      .is_empty());
+  }
+
+  #[test]
+  fn test_notes() {
+    let mut state = test_state();
+    assert!(state.add_note("Codes", Some(("example.com", "alice")), "1 2 3"));
+    assert!(state.add_note("Door", None, "1234"));
+    assert!(!state.add_note("Codes", None, "4 5 6"));
+    assert!(state.set_note_text("Codes", "7 8 9"));
+    assert!(!state.set_note_text("Gate", "0000"));
+    let titles = state
+      .notes_of("example.com", "alice")
+      .map(|n| (n.title.as_ref(), n.text.as_ref()))
+      .collect::<Vec<_>>();
+    assert_eq!(titles, [("Codes", "7 8 9")]);
+    assert!(state.remove_note("Codes").is_some());
+    assert!(state.remove_note("Codes").is_none());
+    assert!(state.find_note("Door").is_some());
+  }
+
+  #[test]
+  fn test_pair_note() {
+    let mut state = test_state();
+    state.set_pair_note("example.com", "alice", "https://login");
+    let note = state.find_note("alice @ example.com").unwrap();
+    assert_eq!(note.text.as_ref(), "https://login");
+    assert_eq!(state.notes_of("example.com", "alice").count(), 1);
+    state.set_pair_note("example.com", "alice", "https://sso");
+    assert_eq!(
+      state
+        .find_note("alice @ example.com")
+        .unwrap()
+        .text
+        .as_ref(),
+      "https://sso"
+    );
+    state.set_pair_note("example.com", "alice", " ");
+    assert!(state.find_note("alice @ example.com").is_none());
+    state.touch("example.com", "alice");
+    state.set_pair_note("example.com", "alice", "https://login");
+    assert!(state.add_note("Door", None, "1234"));
+    assert!(state.forget("example.com", "alice"));
+    assert_eq!(state.notes_of("example.com", "alice").count(), 0);
+    assert!(state.find_note("Door").is_some());
+  }
+
+  #[test]
+  fn test_stored_password() {
+    let mut state = test_state();
+    assert!(!state.clear_stored_password("example.com", "team"));
+    state.set_stored_password("example.com", "team", "hunter2");
+    assert_eq!(
+      state.stored_password("example.com", "team"),
+      Some("hunter2")
+    );
+    assert!(state.clear_stored_password("example.com", "team"));
+    assert_eq!(state.stored_password("example.com", "team"), None);
+    // The pair stays known.
+    assert!(state.find("example.com", "team").is_some());
+  }
+
+  #[test]
+  fn test_tags() {
+    let mut state = test_state();
+    assert!(state.tags("example.com", "team").is_empty());
+    state.set_tags(
+      "example.com",
+      "team",
+      &[
+        "shared".to_owned(),
+        " ".to_owned(),
+        "admin".to_owned(),
+        "shared".to_owned(),
+      ],
+    );
+    assert_eq!(
+      state.tags("example.com", "team"),
+      [Box::from("admin"), Box::from("shared")]
+    );
+    // Setting the tags remembers the pair.
+    assert!(state.find("example.com", "team").is_some());
+  }
+
+  #[test]
+  fn test_import() {
+    let mut state = test_state();
+    assert!(state.import("example.com", "alice", Some("https://login")));
+    assert_eq!(state.find("example.com", "alice").unwrap().count, 0);
+    assert_eq!(
+      state
+        .find_note("alice @ example.com")
+        .unwrap()
+        .text
+        .as_ref(),
+      "https://login"
+    );
+    // The notes already kept win over the imported ones.
+    assert!(!state.import("example.com", "alice", Some("other")));
+    assert_eq!(state.notes_of("example.com", "alice").count(), 1);
+  }
+
+  #[test]
+  fn test_policy_round_trip() {
+    let mut c = Credentials::new("example.com", "alice");
+    c.policy = "words:bip39:5:_".parse().unwrap();
+    let json = serde_json::to_value(&c).unwrap();
+    assert_eq!(json["policy"], "words:bip39:5:_");
+    let c = serde_json::from_value::<Credentials>(json).unwrap();
+    assert_eq!(c.policy.to_string(), "words:bip39:5:_");
+
+    // State files from before the policies default to Base32.
+    let c = serde_json::from_str::<Credentials>(
+      r#"{"domain":"example.com","identity":"alice","count":1}"#,
+    )
+    .unwrap();
+    assert_eq!(c.policy, Policy::default());
+  }
 }
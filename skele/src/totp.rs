@@ -0,0 +1,330 @@
+//! # Time-based one-time passwords
+//!
+//! Computes the codes of RFC 6238 and builds the `otpauth://` URIs that
+//! authenticator apps use for enrollment.  The secrets of the derived codes
+//! come from `KeySource::totp_seed`; the secrets issued by services are
+//! imported from their `otpauth://` URIs.
+
+use hmac::{Hmac, Mac};
+use rustybones::base32;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("not an otpauth URI")]
+  NotOtpauth,
+  #[error("only time-based one-time passwords are supported")]
+  NotTotp,
+  #[error("the URI has no secret")]
+  MissingSecret,
+  #[error("the secret is not valid Base32")]
+  InvalidSecret,
+  #[error("invalid value for parameter {0}")]
+  InvalidParameter(&'static str),
+  #[error("no such imported secret")]
+  NoSuchSecret,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Algorithm {
+  #[default]
+  Sha1,
+  Sha256,
+  Sha512,
+}
+
+impl Algorithm {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Algorithm::Sha1 => "SHA1",
+      Algorithm::Sha256 => "SHA256",
+      Algorithm::Sha512 => "SHA512",
+    }
+  }
+
+  fn from_name(name: &str) -> Option<Self> {
+    match name.to_ascii_uppercase().as_str() {
+      "SHA1" => Some(Algorithm::Sha1),
+      "SHA256" => Some(Algorithm::Sha256),
+      "SHA512" => Some(Algorithm::Sha512),
+      _ => None,
+    }
+  }
+
+  fn mac(&self, key: &[u8], message: &[u8]) -> Vec<u8> {
+    fn mac<M: Mac + hmac::digest::KeyInit>(
+      key: &[u8],
+      message: &[u8],
+    ) -> Vec<u8> {
+      let mut mac = <M as Mac>::new_from_slice(key)
+        .expect("HMAC accepts keys of any length");
+      mac.update(message);
+      mac.finalize().into_bytes().to_vec()
+    }
+    match self {
+      Algorithm::Sha1 => mac::<Hmac<sha1::Sha1>>(key, message),
+      Algorithm::Sha256 => mac::<Hmac<sha2::Sha256>>(key, message),
+      Algorithm::Sha512 => mac::<Hmac<sha2::Sha512>>(key, message),
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Totp {
+  pub secret: Vec<u8>,
+  pub algorithm: Algorithm,
+  pub digits: u32,
+  /// The time step in seconds.
+  pub period: u64,
+}
+
+impl Totp {
+  /// Uses the parameters every authenticator app supports: SHA-1, six digits
+  /// and a 30 second time step.
+  pub fn new(secret: &[u8]) -> Self {
+    Self {
+      secret: secret.to_vec(),
+      algorithm: Algorithm::Sha1,
+      digits: 6,
+      period: 30,
+    }
+  }
+
+  /// Parses an `otpauth://totp/` URI as issued by a service.  Returns the
+  /// label of the URI, usually of the form "issuer:account", along with the
+  /// generator.  The parameters missing from the URI take their usual
+  /// defaults.
+  pub fn from_uri(uri: &str) -> Result<(String, Self), Error> {
+    let rest = uri.trim();
+    let Some(rest) = rest
+      .get(..10)
+      .filter(|scheme| scheme.eq_ignore_ascii_case("otpauth://"))
+      .map(|_| &rest[10..])
+    else {
+      return Err(Error::NotOtpauth);
+    };
+    let (kind, rest) = rest.split_once('/').unwrap_or((rest, ""));
+    if !kind.eq_ignore_ascii_case("totp") {
+      return Err(Error::NotTotp);
+    }
+    let (label, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let label = percent_decode(label).ok_or(Error::NotOtpauth)?;
+    let mut totp = Self::new(&[]);
+    let mut secret = None;
+    for (name, value) in query.split('&').filter_map(|p| p.split_once('=')) {
+      let value =
+        percent_decode(value).ok_or(Error::InvalidParameter("query"))?;
+      match name {
+        "secret" => {
+          secret =
+            Some(base32::from_rfc4648(&value).ok_or(Error::InvalidSecret)?)
+        }
+        "algorithm" => {
+          totp.algorithm = Algorithm::from_name(&value)
+            .ok_or(Error::InvalidParameter("algorithm"))?
+        }
+        "digits" => {
+          totp.digits = value
+            .parse()
+            .ok()
+            .filter(|d| (6..=9).contains(d))
+            .ok_or(Error::InvalidParameter("digits"))?
+        }
+        "period" => {
+          totp.period = value
+            .parse()
+            .ok()
+            .filter(|&p| p > 0)
+            .ok_or(Error::InvalidParameter("period"))?
+        }
+        _ => {}
+      }
+    }
+    match secret {
+      Some(secret) if !secret.is_empty() => totp.secret = secret,
+      _ => return Err(Error::MissingSecret),
+    }
+    Ok((label, totp))
+  }
+
+  /// Returns the code at the given time in seconds since the Unix epoch.
+  pub fn code(&self, unix_time: u64) -> String {
+    let counter = unix_time / self.period;
+    let hash = self.algorithm.mac(&self.secret, &counter.to_be_bytes());
+    // Dynamic truncation of RFC 4226
+    let offset = (hash[hash.len() - 1] & 0x0F) as usize;
+    let binary =
+      u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap())
+        & 0x7FFF_FFFF;
+    let code = binary as u64 % 10u64.pow(self.digits);
+    format!("{:0width$}", code, width = self.digits as usize)
+  }
+
+  /// Returns the number of seconds the code at the given time stays valid.
+  pub fn remaining(&self, unix_time: u64) -> u64 {
+    self.period - unix_time % self.period
+  }
+
+  /// Returns the secret in the Base32 form authenticator apps expect.
+  pub fn secret_base32(&self) -> String {
+    // Pad to whole blocks and drop the characters encoding the padding.
+    let mut octets = self.secret.clone();
+    octets.resize(self.secret.len().div_ceil(5) * 5, 0);
+    let mut encoded = base32::to_rfc4648(&octets);
+    encoded.truncate((self.secret.len() * 8).div_ceil(5));
+    encoded
+  }
+
+  /// Returns the `otpauth://` URI for enrolling the secret.
+  pub fn uri(&self, issuer: &str, account: &str) -> String {
+    format!(
+      "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm={}&digits={}&\
+       period={}",
+      percent_encode(issuer),
+      percent_encode(account),
+      self.secret_base32(),
+      percent_encode(issuer),
+      self.algorithm.name(),
+      self.digits,
+      self.period
+    )
+  }
+}
+
+/// Percent-encodes everything but the unreserved characters of RFC 3986.
+fn percent_encode(s: &str) -> String {
+  s.bytes()
+    .map(|b| match b {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+        (b as char).to_string()
+      }
+      _ => format!("%{:02X}", b),
+    })
+    .collect()
+}
+
+/// Decodes the percent-encoded octets.  Returns `None` if the result is not
+/// valid UTF-8 or an escape is malformed.
+fn percent_decode(s: &str) -> Option<String> {
+  let mut octets = Vec::with_capacity(s.len());
+  let mut bytes = s.bytes();
+  while let Some(b) = bytes.next() {
+    if b == b'%' {
+      let hex = [bytes.next()?, bytes.next()?];
+      octets
+        .push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+    } else {
+      octets.push(b);
+    }
+  }
+  String::from_utf8(octets).ok()
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  /// The test vectors of RFC 6238 appendix B.
+  #[test]
+  fn test_rfc6238_vectors() {
+    let times = [
+      59,
+      1111111109,
+      1111111111,
+      1234567890,
+      2000000000,
+      20000000000,
+    ];
+    let vectors = [
+      (
+        Algorithm::Sha1,
+        &b"12345678901234567890"[..],
+        [
+          "94287082", "07081804", "14050471", "89005924", "69279037",
+          "65353130",
+        ],
+      ),
+      (
+        Algorithm::Sha256,
+        &b"12345678901234567890123456789012"[..],
+        [
+          "46119246", "68084774", "67062674", "91819424", "90698825",
+          "77737706",
+        ],
+      ),
+      (
+        Algorithm::Sha512,
+        &b"1234567890123456789012345678901234567890123456789012345678901234"[..],
+        [
+          "90693936", "25091201", "99943326", "93441116", "38618901",
+          "47863826",
+        ],
+      ),
+    ];
+    for (algorithm, secret, codes) in vectors {
+      let totp = Totp {
+        algorithm,
+        digits: 8,
+        ..Totp::new(secret)
+      };
+      for (time, code) in times.iter().zip(codes) {
+        assert_eq!(totp.code(*time), code, "{:?} at {}", algorithm, time);
+      }
+    }
+  }
+
+  #[test]
+  fn test_uri() {
+    let totp = Totp::new(b"12345678901234567890");
+    assert_eq!(totp.remaining(59), 1);
+    assert_eq!(
+      totp.uri("example.com", "alice@example.com"),
+      "otpauth://totp/example.com:alice%40example.com?\
+       secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=example.com&\
+       algorithm=SHA1&digits=6&period=30"
+    );
+    assert_eq!(Totp::new(b"ab").secret_base32(), "MFRA");
+  }
+
+  #[test]
+  fn test_from_uri() {
+    let (label, totp) = Totp::from_uri(
+      "otpauth://totp/Example%20Corp:alice%40example.com?\
+       secret=gezdgnbvgy3tqojqgezdgnbvgy3tqojq&issuer=Example%20Corp&\
+       algorithm=SHA256&digits=8&period=60",
+    )
+    .unwrap();
+    assert_eq!(label, "Example Corp:alice@example.com");
+    assert_eq!(totp.secret, b"12345678901234567890");
+    assert_eq!(totp.algorithm, Algorithm::Sha256);
+    assert_eq!((totp.digits, totp.period), (8, 60));
+
+    // The round trip through our own URI
+    let derived = Totp::new(b"12345678901234567890");
+    let (_, parsed) =
+      Totp::from_uri(&derived.uri("example.com", "alice")).unwrap();
+    assert_eq!(parsed, derived);
+
+    assert!(matches!(
+      Totp::from_uri("https://example.com/"),
+      Err(Error::NotOtpauth)
+    ));
+    assert!(matches!(
+      Totp::from_uri("otpauth://hotp/x?secret=MZXW6YTB&counter=0"),
+      Err(Error::NotTotp)
+    ));
+    assert!(matches!(
+      Totp::from_uri("otpauth://totp/x?issuer=x"),
+      Err(Error::MissingSecret)
+    ));
+    assert!(matches!(
+      Totp::from_uri("otpauth://totp/x?secret=MZXW6YT1"),
+      Err(Error::InvalidSecret)
+    ));
+    assert!(matches!(
+      Totp::from_uri("otpauth://totp/x?secret=MZXW6YTB&digits=4"),
+      Err(Error::InvalidParameter("digits"))
+    ));
+  }
+}
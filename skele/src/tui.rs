@@ -0,0 +1,621 @@
+//! # Full-screen terminal interface
+//!
+//! An alternative to the prompts for browsing many credentials.  The screen is
+//! split into a searchable list of the known credentials and a pane showing the
+//! details of the selected ones.  After a period of inactivity the interface
+//! locks itself: the key is forgotten and has to be entered again.
+//!
+//! ## Keys
+//!
+//! - `↑`/`k` and `↓`/`j` move the selection, `/` starts searching
+//! - `Enter`/`c` copies the password to the clipboard, `r` reveals it
+//! - `R` rotates the password to the next generation
+//! - `f` forgets the credentials, `n` edits their note
+//! - `l` locks, `q`/`Esc` quits
+
+use std::{
+  io::{self, Write},
+  time::{Duration, Instant},
+};
+
+use crossterm::{
+  cursor::{Hide, MoveTo, Show},
+  event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+  execute, queue,
+  style::{
+    Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor,
+  },
+  terminal::{
+    self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen,
+  },
+};
+use thiserror::Error;
+
+use crate::{
+  clipboard, fingerprint, fuzzy, state, types::Passphrase, KeyContainer,
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("i/o error")]
+  Io(#[from] io::Error),
+  #[error("failed to update key state")]
+  State(#[from] state::Error),
+}
+
+pub struct Options {
+  /// The time of inactivity after which the interface locks itself.
+  pub lock_timeout: Duration,
+  pub clipboard: clipboard::Options,
+}
+
+/// How often the screen is refreshed while waiting for input.
+const TICK: Duration = Duration::from_millis(200);
+
+enum Mode {
+  Browse,
+  Search,
+  ConfirmRotate,
+  ConfirmForget,
+  EditNote(String),
+  Locked(String),
+}
+
+/// A password in the clipboard waiting to be cleared.
+struct Copied {
+  lease: Box<dyn clipboard::Lease>,
+  label: String,
+  deadline: Instant,
+}
+
+/// Puts the terminal into the full-screen mode for as long as it lives.
+struct Screen;
+
+impl Screen {
+  fn enter() -> io::Result<Self> {
+    terminal::enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+    Ok(Self)
+  }
+}
+
+impl Drop for Screen {
+  fn drop(&mut self) {
+    let _ = execute!(io::stdout(), LeaveAlternateScreen, Show);
+    let _ = terminal::disable_raw_mode();
+  }
+}
+
+struct Tui<'a> {
+  app_state: &'a state::AppState,
+  key_container: Option<KeyContainer>,
+  fingerprint: String,
+  options: Options,
+  mode: Mode,
+  search: String,
+  selected: usize,
+  revealed: Option<(Box<str>, Box<str>)>,
+  copied: Option<Copied>,
+  message: Option<String>,
+  last_activity: Instant,
+}
+
+/// Runs the interface until the user quits.
+pub fn run(
+  app_state: &state::AppState,
+  key_container: KeyContainer,
+  options: Options,
+) -> Result<(), Error> {
+  let mut tui = Tui {
+    app_state,
+    fingerprint: key_container.state.public.fingerprint.to_string(),
+    key_container: Some(key_container),
+    options,
+    mode: Mode::Browse,
+    search: String::new(),
+    selected: 0,
+    revealed: None,
+    copied: None,
+    message: None,
+    last_activity: Instant::now(),
+  };
+  let screen = Screen::enter()?;
+  let result = tui.event_loop();
+  tui.clear_clipboard();
+  drop(screen);
+  result
+}
+
+impl Tui<'_> {
+  fn event_loop(&mut self) -> Result<(), Error> {
+    loop {
+      self.draw()?;
+      if event::poll(TICK)? {
+        if let Event::Key(key) = event::read()? {
+          if key.kind == KeyEventKind::Press {
+            self.last_activity = Instant::now();
+            if !self.handle_key(key)? {
+              return Ok(());
+            }
+          }
+        }
+      }
+      self.tick();
+    }
+  }
+
+  /// Takes care of the things that happen with time.
+  fn tick(&mut self) {
+    if let Some(copied) = &mut self.copied {
+      if Instant::now() >= copied.deadline || !copied.lease.is_held() {
+        self.clear_clipboard();
+      }
+    }
+    if self.key_container.is_some()
+      && self.last_activity.elapsed() >= self.options.lock_timeout
+    {
+      self.lock();
+    }
+  }
+
+  fn clear_clipboard(&mut self) {
+    if let Some(mut copied) = self.copied.take() {
+      let held = copied.lease.is_held();
+      self.message = Some(match copied.lease.release() {
+        Ok(()) if held => "Clipboard cleared".to_owned(),
+        Ok(()) => "Clipboard taken over by another application".to_owned(),
+        Err(err) => format!("Clipboard: {}", err),
+      });
+    }
+  }
+
+  fn lock(&mut self) {
+    self.clear_clipboard();
+    self.key_container = None;
+    self.revealed = None;
+    self.mode = Mode::Locked(String::new());
+    self.message = Some("Locked after inactivity".to_owned());
+  }
+
+  fn unlock(&mut self, input: String) -> Result<(), Error> {
+    let skeleton_key: Passphrase = input.into();
+    if fingerprint(&skeleton_key) != self.fingerprint {
+      self.message = Some("Wrong key".to_owned());
+      self.mode = Mode::Locked(String::new());
+      return Ok(());
+    }
+    let key_state = self
+      .app_state
+      .load_key_state(&skeleton_key, &self.fingerprint)?;
+    self.key_container = Some(KeyContainer::new(skeleton_key, key_state));
+    self.mode = Mode::Browse;
+    self.message = None;
+    Ok(())
+  }
+
+  /// Returns the credentials matching the search as domain-identity pairs.
+  fn entries(&self) -> Vec<(Box<str>, Box<str>)> {
+    let Some(key_container) = &self.key_container else {
+      return vec![];
+    };
+    let now = state::unix_time();
+    let credentials = &key_container.state.secret;
+    let found = if self.search.is_empty() {
+      credentials.ranked(now)
+    } else {
+      fuzzy::search(&self.search, credentials.credentials.iter(), now)
+    };
+    found
+      .into_iter()
+      .map(|c| (c.domain.clone(), c.identity.clone()))
+      .collect()
+  }
+
+  fn current(&self) -> Option<(Box<str>, Box<str>)> {
+    self.entries().into_iter().nth(self.selected)
+  }
+
+  /// Keeps the same credentials selected after the list has been reordered.
+  fn reselect(&mut self, domain: &str, identity: &str) {
+    if let Some(ix) = self
+      .entries()
+      .iter()
+      .position(|(d, i)| d.as_ref() == domain && i.as_ref() == identity)
+    {
+      self.selected = ix;
+    }
+  }
+
+  /// Applies the change to the key state as saved on the disk and saves it,
+  /// so that the changes made by other skele invocations since the unlock are
+  /// kept.  Returns `None` if locked.
+  fn update<T>(
+    &mut self,
+    change: impl FnOnce(&mut state::KeyState) -> T,
+  ) -> Result<Option<T>, Error> {
+    let Some(key_container) = &mut self.key_container else {
+      return Ok(None);
+    };
+    let result = self
+      .app_state
+      .update_key_state(&mut key_container.state, change)?;
+    Ok(Some(result))
+  }
+
+  /// Records a use of the credentials and returns their current password.
+  fn use_password(
+    &mut self,
+    domain: &str,
+    identity: &str,
+  ) -> Result<String, Error> {
+    self.update(|state| state.touch(domain, identity))?;
+    let Some(key_container) = &self.key_container else {
+      unreachable!()
+    };
+    let generation = key_container.state.generation(domain, identity);
+    let password = key_container.password(domain, identity, generation);
+    self.reselect(domain, identity);
+    Ok(password)
+  }
+
+  /// Handles the key press.  Returns `false` if the user wants to quit.
+  fn handle_key(&mut self, key: KeyEvent) -> Result<bool, Error> {
+    if key.modifiers.contains(KeyModifiers::CONTROL)
+      && key.code == KeyCode::Char('c')
+    {
+      return Ok(false);
+    }
+    let len = self.entries().len();
+    match &mut self.mode {
+      Mode::Locked(input) => match key.code {
+        KeyCode::Enter => {
+          let input = std::mem::take(input);
+          self.message = Some("Unlocking…".to_owned());
+          self.draw()?;
+          self.unlock(input)?;
+        }
+        KeyCode::Backspace => {
+          input.pop();
+        }
+        KeyCode::Char(c) => input.push(c),
+        KeyCode::Esc => return Ok(false),
+        _ => {}
+      },
+      Mode::Search => match key.code {
+        KeyCode::Enter => self.mode = Mode::Browse,
+        KeyCode::Esc => {
+          self.search.clear();
+          self.selected = 0;
+          self.mode = Mode::Browse;
+        }
+        KeyCode::Backspace => {
+          self.search.pop();
+          self.selected = 0;
+        }
+        KeyCode::Char(c) => {
+          self.search.push(c);
+          self.selected = 0;
+        }
+        KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+        KeyCode::Down => {
+          self.selected = (self.selected + 1).min(len.saturating_sub(1))
+        }
+        _ => {}
+      },
+      Mode::EditNote(note) => match key.code {
+        KeyCode::Enter => {
+          let note = std::mem::take(note);
+          self.mode = Mode::Browse;
+          if let Some((domain, identity)) = self.current() {
+            self.update(|state| {
+              state.set_pair_note(&domain, &identity, note.trim())
+            })?;
+            self.message = Some("Note saved".to_owned());
+          }
+        }
+        KeyCode::Esc => self.mode = Mode::Browse,
+        KeyCode::Backspace => {
+          note.pop();
+        }
+        KeyCode::Char(c) => note.push(c),
+        _ => {}
+      },
+      Mode::ConfirmRotate | Mode::ConfirmForget => {
+        let rotate = matches!(self.mode, Mode::ConfirmRotate);
+        self.mode = Mode::Browse;
+        self.message = None;
+        if key.code == KeyCode::Char('y') {
+          if let Some((domain, identity)) = self.current() {
+            if rotate {
+              if let Some(Some(generation)) =
+                self.update(|state| state.rotate(&domain, &identity))?
+              {
+                self.message =
+                  Some(format!("Rotated to generation {}", generation));
+              }
+            } else if self.update(|state| state.forget(&domain, &identity))?
+              == Some(true)
+            {
+              self.message = Some(format!("Forgot {} @ {}", identity, domain));
+              self.selected = self.selected.min(len.saturating_sub(2));
+            }
+          }
+        }
+      }
+      Mode::Browse => match key.code {
+        KeyCode::Char('q') => return Ok(false),
+        KeyCode::Esc if self.search.is_empty() => return Ok(false),
+        KeyCode::Esc => {
+          self.search.clear();
+          self.selected = 0;
+        }
+        KeyCode::Char('/') => self.mode = Mode::Search,
+        KeyCode::Up | KeyCode::Char('k') => {
+          self.selected = self.selected.saturating_sub(1)
+        }
+        KeyCode::Down | KeyCode::Char('j') => {
+          self.selected = (self.selected + 1).min(len.saturating_sub(1))
+        }
+        KeyCode::Home => self.selected = 0,
+        KeyCode::End => self.selected = len.saturating_sub(1),
+        KeyCode::Char('l') => {
+          self.lock();
+          self.message = None;
+        }
+        _ => {
+          let Some((domain, identity)) = self.current() else {
+            return Ok(true);
+          };
+          match key.code {
+            KeyCode::Enter | KeyCode::Char('c') => {
+              self.clear_clipboard();
+              let password = self.use_password(&domain, &identity)?;
+              self.message = None;
+              match clipboard::acquire(&password, &self.options.clipboard) {
+                Ok(lease) => {
+                  self.copied = Some(Copied {
+                    lease,
+                    label: format!("{} @ {}", identity, domain),
+                    deadline: Instant::now() + self.options.clipboard.timeout,
+                  })
+                }
+                Err(err) => self.message = Some(format!("Clipboard: {}", err)),
+              }
+            }
+            KeyCode::Char('r') => {
+              if self.revealed.as_ref()
+                == Some(&(domain.clone(), identity.clone()))
+              {
+                self.revealed = None;
+              } else {
+                self.use_password(&domain, &identity)?;
+                self.revealed = Some((domain, identity));
+              }
+            }
+            KeyCode::Char('R') => {
+              self.mode = Mode::ConfirmRotate;
+              self.message = Some(format!(
+                "Rotate the password of {} @ {} to the next generation? (y/N)",
+                identity, domain
+              ));
+            }
+            KeyCode::Char('f') => {
+              self.mode = Mode::ConfirmForget;
+              self.message = Some(format!(
+                "Forget {} @ {} and its notes? (y/N)",
+                identity, domain
+              ));
+            }
+            KeyCode::Char('n') => {
+              let note = self
+                .key_container
+                .as_ref()
+                .and_then(|k| {
+                  k.state
+                    .find_note(&state::pair_note_title(&domain, &identity))
+                })
+                .map_or(String::new(), |n| n.text.to_string());
+              self.mode = Mode::EditNote(note);
+            }
+            _ => {}
+          }
+        }
+      },
+    }
+    Ok(true)
+  }
+
+  fn draw(&self) -> io::Result<()> {
+    let (width, height) = terminal::size()?;
+    let (width, height) = (width as usize, height as usize);
+    let mut out = io::stdout().lock();
+    queue!(out, Clear(ClearType::All))?;
+
+    // Title bar
+    queue!(
+      out,
+      MoveTo(0, 0),
+      SetAttribute(Attribute::Reverse),
+      Print(fit(&format!(" Skele  {}", self.fingerprint), width)),
+      SetAttribute(Attribute::Reset)
+    )?;
+
+    if let Mode::Locked(input) = &self.mode {
+      let row = (height / 2) as u16;
+      queue!(
+        out,
+        MoveTo(2, row),
+        SetAttribute(Attribute::Bold),
+        Print("Locked."),
+        SetAttribute(Attribute::Reset),
+        Print(" Enter the skeleton key to unlock: "),
+        Print("*".repeat(input.chars().count())),
+      )?;
+    } else {
+      self.draw_browser(&mut out, width, height)?;
+    }
+
+    // Status line
+    let status = match (&self.mode, &self.copied, &self.message) {
+      (Mode::EditNote(note), _, _) => format!("Note: {}", note),
+      (_, _, Some(message)) => message.clone(),
+      (_, Some(copied), None) => format!(
+        "Copied {}, clearing the clipboard in {} s",
+        copied.label,
+        copied
+          .deadline
+          .saturating_duration_since(Instant::now())
+          .as_secs_f32()
+          .ceil()
+      ),
+      (Mode::Locked(_), _, None) => "Enter unlock  Esc quit".to_owned(),
+      (Mode::Search, _, None) => "Enter done  Esc clear".to_owned(),
+      _ => "/ search  c copy  r reveal  R rotate  f forget  n note  l lock  q \
+            quit"
+        .to_owned(),
+    };
+    queue!(
+      out,
+      MoveTo(0, height.saturating_sub(1) as u16),
+      SetAttribute(Attribute::Reverse),
+      Print(fit(&format!(" {}", status), width)),
+      SetAttribute(Attribute::Reset)
+    )?;
+    out.flush()
+  }
+
+  fn draw_browser(
+    &self,
+    out: &mut impl Write,
+    width: usize,
+    height: usize,
+  ) -> io::Result<()> {
+    let Some(key_container) = &self.key_container else {
+      return Ok(());
+    };
+    queue!(
+      out,
+      MoveTo(0, 1),
+      Print(fit(&format!(" / {}", self.search), width))
+    )?;
+
+    let entries = self.entries();
+    let list_width = (width * 2 / 5).clamp(20.min(width), width);
+    let rows = height.saturating_sub(3);
+    // Scroll so that the selection stays in view.
+    let first = self.selected.saturating_sub(rows.saturating_sub(1));
+    for row in 0..rows {
+      let y = (row + 2) as u16;
+      queue!(out, MoveTo(0, y))?;
+      if let Some((domain, identity)) = entries.get(first + row) {
+        let line = fit(&format!(" {} @ {}", identity, domain), list_width);
+        if first + row == self.selected {
+          queue!(
+            out,
+            SetAttribute(Attribute::Reverse),
+            Print(line),
+            SetAttribute(Attribute::Reset)
+          )?;
+        } else {
+          queue!(out, Print(line))?;
+        }
+      }
+      queue!(out, MoveTo(list_width as u16, y), Print('│'))?;
+    }
+    if entries.is_empty() {
+      queue!(
+        out,
+        MoveTo(1, 2),
+        Print(fit("No credentials found", list_width.saturating_sub(1)))
+      )?;
+    }
+
+    let Some((domain, identity)) = entries.get(self.selected) else {
+      return Ok(());
+    };
+    let Some(c) = key_container.state.find(domain, identity) else {
+      return Ok(());
+    };
+    let x = (list_width + 2) as u16;
+    let detail_width = width.saturating_sub(list_width + 2);
+    let now = state::unix_time();
+    let password =
+      if self.revealed.as_ref() == Some(&(domain.clone(), identity.clone())) {
+        key_container.password(domain, identity, c.generation)
+      } else {
+        "(press r to reveal)".to_owned()
+      };
+    // Only the titles, as the notes are secret.
+    let notes = key_container
+      .state
+      .notes_of(domain, identity)
+      .map(|n| n.title.as_ref())
+      .collect::<Vec<_>>()
+      .join(", ");
+    let notes = if notes.is_empty() {
+      "-".to_owned()
+    } else {
+      notes
+    };
+    let fields = [
+      ("Domain", c.domain.to_string()),
+      ("Identity", c.identity.to_string()),
+      ("Generation", c.generation.to_string()),
+      ("Policy", key_container.policy(domain, identity).to_string()),
+      ("Uses", c.count.to_string()),
+      ("Last used", ago(now.saturating_sub(c.last_used))),
+      ("Notes", notes),
+      ("Password", password),
+    ];
+    for (row, (name, value)) in fields.iter().enumerate() {
+      queue!(
+        out,
+        MoveTo(x, (row + 2) as u16),
+        SetForegroundColor(Color::Green),
+        Print(fit(&format!("{}:", name), 12.min(detail_width))),
+        ResetColor,
+        Print(fit(value, detail_width.saturating_sub(12)))
+      )?;
+    }
+    Ok(())
+  }
+}
+
+/// Truncates or pads the string to exactly `width` characters.
+fn fit(s: &str, width: usize) -> String {
+  let mut fitted = s.chars().take(width).collect::<String>();
+  let len = fitted.chars().count();
+  fitted.extend(std::iter::repeat_n(' ', width - len));
+  fitted
+}
+
+/// Describes the age given in seconds in human terms.
+fn ago(secs: u64) -> String {
+  match secs {
+    0..60 => "just now".to_owned(),
+    60..3600 => format!("{} min ago", secs / 60),
+    3600..86400 => format!("{} h ago", secs / 3600),
+    _ => format!("{} days ago", secs / 86400),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn test_fit() {
+    assert_eq!(fit("alice", 8), "alice   ");
+    assert_eq!(fit("äiti @ example.com", 4), "äiti");
+    assert_eq!(fit("", 0), "");
+  }
+
+  #[test]
+  fn test_ago() {
+    assert_eq!(ago(5), "just now");
+    assert_eq!(ago(125), "2 min ago");
+    assert_eq!(ago(7200), "2 h ago");
+    assert_eq!(ago(3 * 86400 + 5), "3 days ago");
+  }
+}
@@ -0,0 +1,131 @@
+//! Checks the machine-readable output of the commands against the snapshots
+//! in `tests/data/snapshots`.  Set `SKELE_UPDATE_SNAPSHOTS` to rewrite them.
+
+use std::{
+  env, fs,
+  io::{BufRead, BufReader, Write},
+  os::unix::net::UnixListener,
+  path::{Path, PathBuf},
+  process::{self, Command, Output},
+  thread,
+};
+
+use serde_json::{json, Value};
+
+fn temp_dir(name: &str) -> PathBuf {
+  let dir = env::temp_dir().join(format!(
+    "skele-format-test-{}-{}",
+    process::id(),
+    name
+  ));
+  fs::create_dir_all(&dir).unwrap();
+  dir
+}
+
+/// Answers the agent requests like an agent that has unlocked a key.
+fn fake_agent(listener: UnixListener) {
+  for stream in listener.incoming() {
+    let stream = stream.unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    for line in BufReader::new(stream).lines() {
+      let request: Value = serde_json::from_str(&line.unwrap()).unwrap();
+      let response = match request["op"].as_str().unwrap() {
+        "status" => json!({
+          "result": "status",
+          "fingerprint": "Abcd-Efgh-Jkmn-Pqrs",
+          "idle_timeout": 900,
+        }),
+        "get" => json!({
+          "result": "password",
+          "password": "5Wsc-X2mz-Csnc-4Vgc",
+        }),
+        "lock" => json!({ "result": "locked" }),
+        op => panic!("unexpected agent request {}", op),
+      };
+      writeln!(writer, "{}", response).unwrap();
+    }
+  }
+}
+
+fn skele(dir: &Path, args: &[&str]) -> Output {
+  Command::new(env!("CARGO_BIN_EXE_skele"))
+    .args(["--format", "json"])
+    .args(args)
+    .env("HOME", dir)
+    .env("XDG_DATA_HOME", dir.join("data"))
+    .env("SKELE_AGENT_SOCKET", dir.join("agent.sock"))
+    .output()
+    .unwrap()
+}
+
+fn assert_snapshot(name: &str, dir: &Path, output: &Output) {
+  let actual = String::from_utf8(output.stdout.clone())
+    .unwrap()
+    .replace(dir.to_str().unwrap(), "$DIR");
+  let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+    .join("tests/data/snapshots")
+    .join(name);
+  if env::var_os("SKELE_UPDATE_SNAPSHOTS").is_some() {
+    fs::write(&path, &actual).unwrap();
+  }
+  assert_eq!(actual, fs::read_to_string(&path).unwrap());
+}
+
+#[test]
+fn test_commands_with_agent() {
+  let dir = temp_dir("agent");
+  let listener = UnixListener::bind(dir.join("agent.sock")).unwrap();
+  thread::spawn(move || fake_agent(listener));
+
+  let output = skele(&dir, &["get", "example.com", "alice", "--reveal"]);
+  assert!(output.status.success());
+  assert_snapshot("get_reveal.jsonl", &dir, &output);
+
+  let output = skele(&dir, &["lock"]);
+  assert!(output.status.success());
+  assert_snapshot("lock.jsonl", &dir, &output);
+  let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_commands_without_agent() {
+  let dir = temp_dir("no-agent");
+
+  let output = skele(&dir, &["lock"]);
+  assert!(output.status.success());
+  assert_snapshot("lock_not_running.jsonl", &dir, &output);
+
+  let output = skele(&dir, &[]);
+  assert_eq!(output.status.code(), Some(1));
+  assert_snapshot("interactive.jsonl", &dir, &output);
+  let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_weak_pin_is_refused() {
+  let dir = temp_dir("weak-pin");
+
+  // Refused before the key is asked for.
+  let output = skele(&dir, &["policy", "example.com", "alice", "pin:6"]);
+  assert_eq!(output.status.code(), Some(1));
+  assert_snapshot("policy_weak_pin.jsonl", &dir, &output);
+  let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_error_in_text_format() {
+  let dir = temp_dir("text-error");
+
+  let output = Command::new(env!("CARGO_BIN_EXE_skele"))
+    .args(["breaches", dir.join("missing").to_str().unwrap()])
+    .env("HOME", &dir)
+    .env("XDG_DATA_HOME", dir.join("data"))
+    .output()
+    .unwrap();
+  assert_eq!(output.status.code(), Some(1));
+  let stderr = String::from_utf8(output.stderr).unwrap();
+  assert!(stderr.contains("Error:"));
+  assert!(!stderr.contains("panicked"));
+  assert!(!String::from_utf8(output.stdout).unwrap().contains("Error:"));
+  let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,162 @@
+//! Drives `skele native-host` with recorded browser messages against a fake
+//! agent.
+
+use std::{
+  env, fs,
+  io::{BufRead, BufReader, Read, Write},
+  os::unix::net::{UnixListener, UnixStream},
+  path::PathBuf,
+  process::{self, Child, ChildStdin, ChildStdout, Command, Stdio},
+  thread,
+};
+
+use serde_json::{json, Value};
+
+const SESSION: &str = include_str!("data/native_host_session.json");
+
+fn socket_path(name: &str) -> PathBuf {
+  let dir =
+    env::temp_dir().join(format!("skele-test-{}-{}", process::id(), name));
+  fs::create_dir_all(&dir).unwrap();
+  dir.join("agent.sock")
+}
+
+/// Answers the agent requests from the recorded agent state.
+fn fake_agent(listener: UnixListener, agent: Value) {
+  for stream in listener.incoming() {
+    let stream = stream.unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    for line in BufReader::new(stream).lines() {
+      let request: Value = serde_json::from_str(&line.unwrap()).unwrap();
+      let response = match request["op"].as_str().unwrap() {
+        "list" => json!({
+          "result": "list",
+          "credentials": agent["credentials"],
+        }),
+        "get" => {
+          let key = format!(
+            "{} {}",
+            request["domain"].as_str().unwrap(),
+            request["identity"].as_str().unwrap()
+          );
+          json!({
+            "result": "password",
+            "password": agent["passwords"][key],
+          })
+        }
+        op => panic!("unexpected agent request {}", op),
+      };
+      writeln!(writer, "{}", response).unwrap();
+    }
+  }
+}
+
+fn spawn_host(socket_path: &PathBuf, args: &[&str]) -> Child {
+  Command::new(env!("CARGO_BIN_EXE_skele"))
+    .arg("native-host")
+    .args(args)
+    .arg("chrome-extension://skele-test/")
+    .env("SKELE_AGENT_SOCKET", socket_path)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .spawn()
+    .unwrap()
+}
+
+fn exchange(
+  stdin: &mut ChildStdin,
+  stdout: &mut ChildStdout,
+  request: &Value,
+) -> Value {
+  let message = serde_json::to_vec(request).unwrap();
+  stdin
+    .write_all(&(message.len() as u32).to_ne_bytes())
+    .unwrap();
+  stdin.write_all(&message).unwrap();
+  stdin.flush().unwrap();
+  let mut len = [0u8; 4];
+  stdout.read_exact(&mut len).unwrap();
+  let mut response = vec![0u8; u32::from_ne_bytes(len) as usize];
+  stdout.read_exact(&mut response).unwrap();
+  serde_json::from_slice(&response).unwrap()
+}
+
+#[test]
+fn test_recorded_session() {
+  let session: Value = serde_json::from_str(SESSION).unwrap();
+  let socket_path = socket_path("recorded-session");
+  let listener = UnixListener::bind(&socket_path).unwrap();
+  let agent = session["agent"].clone();
+  thread::spawn(move || fake_agent(listener, agent));
+
+  let mut host = spawn_host(&socket_path, &[]);
+  let mut stdin = host.stdin.take().unwrap();
+  let mut stdout = host.stdout.take().unwrap();
+  for recorded in session["exchanges"].as_array().unwrap() {
+    let response = exchange(&mut stdin, &mut stdout, &recorded["request"]);
+    assert_eq!(response, recorded["response"]);
+  }
+  drop(stdin);
+  assert!(host.wait().unwrap().success());
+  let _ = fs::remove_dir_all(socket_path.parent().unwrap());
+}
+
+#[test]
+fn test_subdomains() {
+  let session: Value = serde_json::from_str(SESSION).unwrap();
+  let socket_path = socket_path("subdomains");
+  let listener = UnixListener::bind(&socket_path).unwrap();
+  let agent = session["agent"].clone();
+  thread::spawn(move || fake_agent(listener, agent));
+
+  let mut host = spawn_host(&socket_path, &["--subdomains"]);
+  let mut stdin = host.stdin.take().unwrap();
+  let mut stdout = host.stdout.take().unwrap();
+  let response = exchange(
+    &mut stdin,
+    &mut stdout,
+    &json!({
+      "type": "password",
+      "url": "https://www.example.org/login",
+      "identity": "alice@example.org",
+    }),
+  );
+  assert_eq!(response["password"], "Stvw-Xyz0-1234-5678");
+  let response = exchange(
+    &mut stdin,
+    &mut stdout,
+    &json!({ "type": "identities", "url": "https://api.github.com/" }),
+  );
+  assert_eq!(response["domain"], "github.com");
+  let response = exchange(
+    &mut stdin,
+    &mut stdout,
+    &json!({ "type": "identities", "url": "https://gist.github.com/" }),
+  );
+  assert_eq!(response["domain"], "gist.github.com");
+  drop(stdin);
+  assert!(host.wait().unwrap().success());
+  let _ = fs::remove_dir_all(socket_path.parent().unwrap());
+}
+
+#[test]
+fn test_refuses_without_agent() {
+  let socket_path = socket_path("no-agent");
+  assert!(UnixStream::connect(&socket_path).is_err());
+
+  let mut host = spawn_host(&socket_path, &[]);
+  let mut stdin = host.stdin.take().unwrap();
+  let mut stdout = host.stdout.take().unwrap();
+  let response = exchange(
+    &mut stdin,
+    &mut stdout,
+    &json!({ "type": "identities", "url": "https://github.com/" }),
+  );
+  assert_eq!(
+    response,
+    json!({ "type": "error", "message": "agent is not running" })
+  );
+  drop(stdin);
+  assert!(host.wait().unwrap().success());
+  let _ = fs::remove_dir_all(socket_path.parent().unwrap());
+}